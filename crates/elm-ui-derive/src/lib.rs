@@ -0,0 +1,243 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, parse_macro_input};
+
+/// Implements `TypedMessage` and `From<T> for Message` so the type can be sent as a
+/// `Message::Custom` and matched in `Model::update` without manual downcasting.
+///
+/// With `#[typed_message(debug)]`, messages are formatted with the type's `Debug`
+/// implementation instead of just its name.
+///
+/// ```
+/// use elm_ui::{Message, TypedMessage};
+///
+/// #[derive(Debug, PartialEq, TypedMessage)]
+/// #[typed_message(debug)]
+/// enum Counter {
+///     Increment,
+///     Decrement,
+/// }
+///
+/// let msg = Message::from(Counter::Increment);
+/// assert_eq!(format!("{msg:?}"), "Custom(Increment)");
+/// assert_eq!(Counter::from_message(msg).ok(), Some(Counter::Increment));
+/// ```
+#[proc_macro_derive(TypedMessage, attributes(typed_message))]
+pub fn derive_typed_message(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_typed_message(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `MessageSet` and `From<T> for Message` for an enum with one variant for each
+/// message type, so messages of several types can be handled with a single `match`.
+///
+/// Each variant must wrap exactly one type that implements `TypedMessage`. The types are tried
+/// in the order the variants are declared.
+///
+/// ```
+/// use elm_ui::{Message, MessageSet, TypedMessage};
+///
+/// #[derive(TypedMessage)]
+/// struct Tick(u32);
+///
+/// #[derive(TypedMessage)]
+/// struct Reset;
+///
+/// #[derive(MessageSet)]
+/// enum AppMessage {
+///     Tick(Tick),
+///     Reset(Reset),
+/// }
+///
+/// fn update(count: &mut u32, msg: Message) {
+///     match AppMessage::from_message(msg) {
+///         Ok(AppMessage::Tick(Tick(n))) => *count += n,
+///         Ok(AppMessage::Reset(Reset)) => *count = 0,
+///         Err(_other) => {}
+///     }
+/// }
+///
+/// let mut count = 0;
+/// update(&mut count, Tick(2).into());
+/// update(&mut count, Tick(3).into());
+/// assert_eq!(count, 5);
+/// update(&mut count, Reset.into());
+/// assert_eq!(count, 0);
+/// ```
+#[proc_macro_derive(MessageSet)]
+pub fn derive_message_set(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_message_set(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_typed_message(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut debug = false;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("typed_message"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("debug") {
+                debug = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported typed_message attribute"))
+            }
+        })?;
+    }
+    let constructor = if debug {
+        quote!(::elm_ui::Message::custom_debug)
+    } else {
+        quote!(::elm_ui::Message::custom)
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::elm_ui::TypedMessage for #name #ty_generics #where_clause {
+            fn into_message(self) -> ::elm_ui::Message {
+                #constructor(self)
+            }
+        }
+
+        impl #impl_generics ::core::convert::From<#name #ty_generics> for ::elm_ui::Message
+            #where_clause
+        {
+            fn from(msg: #name #ty_generics) -> Self {
+                #constructor(msg)
+            }
+        }
+    })
+}
+
+fn expand_message_set(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let Data::Enum(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "MessageSet can only be derived for enums",
+        ));
+    };
+    let mut variants = Vec::new();
+    let mut types = Vec::new();
+    for variant in &data.variants {
+        match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                variants.push(&variant.ident);
+                types.push(&fields.unnamed[0].ty);
+            }
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "MessageSet variants must wrap exactly one message type",
+                ));
+            }
+        }
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::elm_ui::MessageSet for #name #ty_generics #where_clause {
+            fn from_message(
+                msg: ::elm_ui::Message,
+            ) -> ::core::result::Result<Self, ::elm_ui::Message> {
+                #(
+                    let msg = match <#types as ::elm_ui::TypedMessage>::from_message(msg) {
+                        ::core::result::Result::Ok(value) => {
+                            return ::core::result::Result::Ok(Self::#variants(value));
+                        }
+                        ::core::result::Result::Err(msg) => msg,
+                    };
+                )*
+                ::core::result::Result::Err(msg)
+            }
+
+            fn into_message(self) -> ::elm_ui::Message {
+                match self {
+                    #(Self::#variants(value) => ::elm_ui::TypedMessage::into_message(value),)*
+                }
+            }
+        }
+
+        impl #impl_generics ::core::convert::From<#name #ty_generics> for ::elm_ui::Message
+            #where_clause
+        {
+            fn from(msg: #name #ty_generics) -> Self {
+                ::elm_ui::MessageSet::into_message(msg)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use super::{expand_message_set, expand_typed_message};
+
+    #[test]
+    fn rejects_unknown_typed_message_attributes() {
+        let err = expand_typed_message(parse_quote! {
+            #[typed_message(debug, clone)]
+            struct Tick;
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "unsupported typed_message attribute");
+    }
+
+    #[test]
+    fn accepts_the_debug_attribute() {
+        let tokens = expand_typed_message(parse_quote! {
+            #[derive(Debug)]
+            #[typed_message(debug)]
+            struct Tick;
+        })
+        .unwrap();
+        assert!(tokens.to_string().contains("custom_debug"));
+    }
+
+    #[test]
+    fn message_sets_must_be_enums() {
+        let err = expand_message_set(parse_quote! {
+            struct Messages(Tick);
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "MessageSet can only be derived for enums");
+    }
+
+    #[test]
+    fn message_set_variants_wrap_one_type() {
+        for input in [
+            parse_quote!(
+                enum Messages {
+                    Tick(Tick),
+                    Reset,
+                }
+            ),
+            parse_quote!(
+                enum Messages {
+                    Tick(Tick, Reset),
+                }
+            ),
+            parse_quote!(
+                enum Messages {
+                    Tick { tick: Tick },
+                }
+            ),
+        ] {
+            let err = expand_message_set(input).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "MessageSet variants must wrap exactly one message type"
+            );
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use elm_ui::{Message, MessageSet, TypedMessage};
+
+#[derive(Debug, PartialEq, TypedMessage)]
+#[typed_message(debug)]
+struct Tick(u32);
+
+#[derive(Debug, PartialEq, TypedMessage)]
+struct Opaque;
+
+#[derive(Debug, PartialEq, TypedMessage)]
+#[typed_message(debug)]
+struct Wrapper<T: std::fmt::Debug + Send + 'static>(T);
+
+#[derive(Debug, PartialEq, MessageSet)]
+enum AppMessage {
+    Tick(Tick),
+    Opaque(Opaque),
+}
+
+#[test]
+fn typed_messages_round_trip() {
+    let msg = Message::from(Tick(3));
+    assert_eq!(Tick::from_message_ref(&msg), Some(&Tick(3)));
+    assert_eq!(Tick::from_message(msg).ok(), Some(Tick(3)));
+
+    let msg = Opaque::from_message(Tick(4).into()).unwrap_err();
+    assert_eq!(Tick::from_message(msg).ok(), Some(Tick(4)));
+    assert!(Tick::from_message(Message::CancelAll).is_err());
+}
+
+#[test]
+fn debug_attribute_formats_the_payload() {
+    assert_eq!(format!("{:?}", Message::from(Tick(3))), "Custom(Tick(3))");
+    assert_eq!(
+        format!("{:?}", Message::from(Wrapper("text"))),
+        r#"Custom(Wrapper("text"))"#
+    );
+
+    let opaque = format!("{:?}", Message::from(Opaque));
+    assert!(opaque.starts_with("Custom("), "{opaque}");
+    assert!(opaque.ends_with("::Opaque { .. })"), "{opaque}");
+}
+
+#[test]
+fn message_sets_match_each_type() {
+    assert_eq!(
+        AppMessage::from_message(Tick(1).into()).ok(),
+        Some(AppMessage::Tick(Tick(1)))
+    );
+    assert_eq!(
+        AppMessage::from_message(Opaque.into()).ok(),
+        Some(AppMessage::Opaque(Opaque))
+    );
+
+    let msg = AppMessage::from_message(Wrapper(2).into()).unwrap_err();
+    assert_eq!(Wrapper::<i32>::from_message(msg).ok(), Some(Wrapper(2)));
+}
+
+#[test]
+fn message_sets_convert_to_the_wrapped_message() {
+    let msg = Message::from(AppMessage::Tick(Tick(5)));
+    assert_eq!(Tick::from_message(msg).ok(), Some(Tick(5)));
+
+    let msg = AppMessage::Opaque(Opaque).into_message();
+    assert_eq!(Opaque::from_message(msg).ok(), Some(Opaque));
+}
@@ -1,9 +1,9 @@
 use elm_ui::{
-    Command, Message, Model, Program, ProgramError, QuitBehavior,
+    Command, Message, Model, Program, ProgramError, ProgramExit, QuitBehavior,
     future_ext::{CancelledByShutdown, FutureExt},
 };
 #[cfg(feature = "tui")]
-use ratatui::{Terminal, backend::TestBackend, buffer::Buffer, layout::Rect};
+use ratatui::{Terminal, backend::TestBackend, buffer::Buffer, layout::Rect, style::Color};
 use std::{
     sync::{Arc, RwLock},
     thread,
@@ -12,13 +12,21 @@ use std::{
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
+pub mod snapshot;
+mod virtual_time;
+
+pub use virtual_time::VirtualUiTester;
+
+type TestResult<M> = Result<Result<ProgramExit<M>, ProgramError<M>>, CancelledByShutdown>;
+
 pub struct UiTester<M: Model + Send + 'static, O: Clone + Send + Sync + 'static>
 where
     M::Writer: Send + 'static,
 {
     cmd_tx: mpsc::Sender<Command>,
+    msg_tx: mpsc::Sender<Message>,
     term_view: Arc<RwLock<O>>,
-    handle: thread::JoinHandle<Result<Result<M, ProgramError<M>>, CancelledByShutdown>>,
+    handle: thread::JoinHandle<TestResult<M>>,
     cancellation_token: CancellationToken,
 }
 
@@ -27,11 +35,12 @@ impl<M: Model + Send + 'static + Send + 'static> UiTester<M, Buffer>
 where
     M: Model<Writer = Terminal<TestBackend>> + Send + 'static,
 {
-    pub fn new_tui(model: M, writer: M::Writer) -> Self
+    pub fn new_tui(model: M, flags: M::Flags, writer: M::Writer) -> Self
     where
         <M as elm_ui::Model>::Error: std::marker::Send,
+        M::Flags: Send,
     {
-        Self::new(model, writer, |o| o.backend().buffer().clone())
+        Self::new(model, flags, writer, |o| o.backend().buffer().clone())
     }
 }
 
@@ -41,50 +50,59 @@ where
 {
     pub fn new(
         model: M,
+        flags: M::Flags,
         mut writer: M::Writer,
         mut get_output: impl FnMut(&mut M::Writer) -> O + Send + 'static,
     ) -> Self
     where
         <M as elm_ui::Model>::Error: std::marker::Send,
+        M::Flags: Send,
     {
-        let mut program = Program::new(model).with_spawn_event_handler(false);
+        let mut program = Program::builder(model, flags)
+            .with_spawn_event_handler(false)
+            .build();
         let cmd_tx = program.cmd_tx();
+        let msg_tx = program.msg_tx();
         let term_view = Arc::new(RwLock::new(get_output(&mut writer)));
         let term_view_ = term_view.clone();
         let cancellation_token = CancellationToken::new();
         let cancellation_token_ = cancellation_token.clone();
-        let handle: thread::JoinHandle<Result<Result<M, ProgramError<M>>, CancelledByShutdown>> =
-            thread::spawn(move || {
-                tokio::runtime::Runtime::new()
-                    .unwrap()
-                    .block_on(async move {
-                        let local = tokio::task::LocalSet::new();
-                        local
-                            .run_until(async move {
-                                program.initialize().await?;
+        let handle: thread::JoinHandle<TestResult<M>> = thread::spawn(move || {
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(async move {
+                    let local = tokio::task::LocalSet::new();
+                    local
+                        .run_until(async move {
+                            program.initialize().await?;
+                            program
+                                .view(&mut writer)
+                                .map_err(ProgramError::<M>::ApplicationFailure)?;
+                            while let Some(msg) = program.recv_msg().await? {
+                                let quit_behavior = program.update(msg).await?;
                                 program
                                     .view(&mut writer)
                                     .map_err(ProgramError::<M>::ApplicationFailure)?;
-                                while let Some(msg) = program.recv_msg().await {
-                                    let quit_behavior = program.update(msg).await?;
-                                    program
-                                        .view(&mut writer)
-                                        .map_err(ProgramError::<M>::ApplicationFailure)?;
-                                    (*term_view_.write().unwrap()) = get_output(&mut writer);
-
-                                    if quit_behavior == QuitBehavior::Quit {
-                                        return Ok(program.shutdown().await);
-                                    }
+                                (*term_view_.write().unwrap()) = get_output(&mut writer);
+
+                                if quit_behavior == QuitBehavior::Quit {
+                                    break;
                                 }
+                            }
 
-                                Ok(program.into_model())
-                            })
-                            .cancel_on_shutdown(&cancellation_token_)
-                            .await
-                    })
-            });
+                            let exit = program.shutdown().await?;
+                            if exit.report.has_failures() {
+                                return Err(ProgramError::ShutdownFailure(exit.report));
+                            }
+                            Ok(exit)
+                        })
+                        .cancel_on_shutdown(&cancellation_token_)
+                        .await
+                })
+        });
         Self {
             cmd_tx,
+            msg_tx,
             handle,
             term_view,
             cancellation_token,
@@ -96,7 +114,7 @@ where
     }
 
     pub async fn send_msg(&self, msg: Message) {
-        self.cmd_tx.send(Command::simple(msg)).await.unwrap();
+        self.msg_tx.send(msg).await.unwrap();
     }
 
     #[cfg(feature = "crossterm")]
@@ -126,14 +144,14 @@ where
         }
     }
 
-    pub fn wait_for_completion(self) -> Result<(M, O), ProgramError<M>> {
+    pub fn wait_for_completion(self) -> Result<(ProgramExit<M>, O), ProgramError<M>> {
         let cancel_task = tokio::task::spawn(async move {
             tokio::time::sleep(Duration::from_secs(5)).await;
             self.cancellation_token.cancel();
         });
-        let model = self.handle.join().unwrap().expect("Failed to shut down")?;
+        let exit = self.handle.join().unwrap().expect("Failed to shut down")?;
         cancel_task.abort();
-        Ok((model, self.term_view.read().unwrap().clone()))
+        Ok((exit, self.term_view.read().unwrap().clone()))
     }
 }
 
@@ -142,6 +160,16 @@ pub trait TerminalView {
     fn terminal_view(&self) -> String;
 }
 
+/// Like [`TerminalView`], but includes the style of each cell.
+#[cfg(feature = "tui")]
+pub trait StyledTerminalView: TerminalView {
+    /// Like [`TerminalView::terminal_view`], but each row is followed by a line for every run
+    /// of cells that share a style other than the default, e.g.
+    /// `  ~ 0..12 fg=Black bg=LightGreen ul=Red mod=BOLD|UNDERLINED`. Runs are given as
+    /// half-open column ranges, and colors or modifiers that aren't set are left out.
+    fn styled_terminal_view(&self) -> String;
+}
+
 #[cfg(feature = "tui")]
 impl TerminalView for Buffer {
     fn terminal_view(&self) -> String {
@@ -159,3 +187,55 @@ impl TerminalView for Buffer {
         string_buf
     }
 }
+
+#[cfg(feature = "tui")]
+impl StyledTerminalView for Buffer {
+    fn styled_terminal_view(&self) -> String {
+        use std::fmt::Write;
+
+        let Rect { width, height, .. } = self.area();
+        let mut string_buf = String::with_capacity((width * height) as usize);
+        for row in 0..*height {
+            let mut runs = Vec::new();
+            for col in 0..*width {
+                let cell = &self[(col, row)];
+                write!(&mut string_buf, "{}", cell.symbol()).unwrap();
+                let style = (cell.fg, cell.bg, cell.underline_color, cell.modifier);
+                match runs.last_mut() {
+                    Some((_, end, run_style)) if *end == col && *run_style == style => *end += 1,
+                    _ => runs.push((col, col + 1, style)),
+                }
+            }
+            writeln!(&mut string_buf).unwrap();
+            for (start, end, (fg, bg, underline, modifier)) in runs {
+                if fg == Color::Reset
+                    && bg == Color::Reset
+                    && underline == Color::Reset
+                    && modifier.is_empty()
+                {
+                    continue;
+                }
+                write!(&mut string_buf, "  ~ {start}..{end}").unwrap();
+                if fg != Color::Reset {
+                    write!(&mut string_buf, " fg={fg}").unwrap();
+                }
+                if bg != Color::Reset {
+                    write!(&mut string_buf, " bg={bg}").unwrap();
+                }
+                if underline != Color::Reset {
+                    write!(&mut string_buf, " ul={underline}").unwrap();
+                }
+                if !modifier.is_empty() {
+                    write!(
+                        &mut string_buf,
+                        " mod={}",
+                        format!("{modifier:?}").replace(" | ", "|")
+                    )
+                    .unwrap();
+                }
+                writeln!(&mut string_buf).unwrap();
+            }
+        }
+        string_buf
+    }
+}
@@ -0,0 +1,220 @@
+use std::{env, fmt::Write, fs, path::Path};
+
+#[cfg(feature = "tui")]
+use ratatui::buffer::Buffer;
+
+#[cfg(feature = "tui")]
+use crate::{StyledTerminalView, TerminalView};
+
+/// Set to any value other than `0` to write the actual output to the snapshot files instead of
+/// comparing against them.
+pub const UPDATE_SNAPSHOTS_VAR: &str = "ELM_UI_UPDATE_SNAPSHOTS";
+
+/// Output that can be compared against a stored snapshot.
+pub trait Snapshot {
+    fn snapshot(&self) -> String;
+}
+
+impl<T: Snapshot + ?Sized> Snapshot for &T {
+    fn snapshot(&self) -> String {
+        (**self).snapshot()
+    }
+}
+
+impl Snapshot for str {
+    fn snapshot(&self) -> String {
+        self.to_owned()
+    }
+}
+
+impl Snapshot for String {
+    fn snapshot(&self) -> String {
+        self.clone()
+    }
+}
+
+#[cfg(feature = "tui")]
+impl Snapshot for Buffer {
+    fn snapshot(&self) -> String {
+        self.terminal_view()
+    }
+}
+
+/// Snapshots a buffer with its styles, using [`StyledTerminalView::styled_terminal_view`].
+#[cfg(feature = "tui")]
+pub struct Styled<'a>(pub &'a Buffer);
+
+#[cfg(feature = "tui")]
+impl Snapshot for Styled<'_> {
+    fn snapshot(&self) -> String {
+        self.0.styled_terminal_view()
+    }
+}
+
+/// Compares `actual` against the snapshot stored in `snapshots/<name>.snap`, relative to the
+/// manifest directory of the crate being tested.
+///
+/// ```ignore
+/// let (_, buffer) = tester.wait_for_completion()?;
+/// assert_snapshot!("list_selected", buffer);
+/// assert_snapshot!("list_selected_styled", Styled(&buffer));
+/// ```
+#[macro_export]
+macro_rules! assert_snapshot {
+    ($name:expr, $actual:expr) => {
+        $crate::snapshot::assert_snapshot_in(
+            ::std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("snapshots"),
+            $name,
+            &$actual,
+        )
+    };
+}
+
+/// Compares `actual` against the snapshot stored in `<dir>/<name>.snap`.
+///
+/// If [`UPDATE_SNAPSHOTS_VAR`] is set, the snapshot is written instead.
+///
+/// # Panics
+///
+/// Panics with a line diff if the output doesn't match, or if the snapshot doesn't exist.
+#[track_caller]
+pub fn assert_snapshot_in(dir: impl AsRef<Path>, name: &str, actual: &(impl Snapshot + ?Sized)) {
+    let path = dir.as_ref().join(format!("{name}.snap"));
+    let actual = actual.snapshot();
+    if env::var_os(UPDATE_SNAPSHOTS_VAR).is_some_and(|value| value != "0") {
+        fs::create_dir_all(dir.as_ref()).unwrap_or_else(|e| {
+            panic!(
+                "failed to create snapshot directory {}: {e}",
+                dir.as_ref().display()
+            )
+        });
+        fs::write(&path, &actual)
+            .unwrap_or_else(|e| panic!("failed to write snapshot {}: {e}", path.display()));
+        return;
+    }
+    let expected = match fs::read_to_string(&path) {
+        Ok(expected) => expected,
+        Err(e) => panic!(
+            "failed to read snapshot {}: {e}\nrun with {UPDATE_SNAPSHOTS_VAR}=1 to create it\n\n\
+             actual:\n{actual}",
+            path.display()
+        ),
+    };
+    if expected != actual {
+        panic!(
+            "snapshot '{name}' doesn't match {}\nrun with {UPDATE_SNAPSHOTS_VAR}=1 to update \
+             it\n\n{}",
+            path.display(),
+            line_diff(&expected, &actual)
+        );
+    }
+}
+
+/// Formats a line-by-line diff from `expected` to `actual`. Line ends are marked so trailing
+/// whitespace differences are visible.
+fn line_diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<_> = expected.lines().collect();
+    let actual: Vec<_> = actual.lines().collect();
+
+    // Longest common subsequence lengths of every pair of suffixes
+    let mut lengths = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lengths[i][j] = if expected[i] == actual[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            writeln!(diff, "  {}|", expected[i]).unwrap();
+            i += 1;
+            j += 1;
+        } else if i < expected.len()
+            && (j == actual.len() || lengths[i + 1][j] >= lengths[i][j + 1])
+        {
+            writeln!(diff, "- {}|", expected[i]).unwrap();
+            i += 1;
+        } else {
+            writeln!(diff, "+ {}|", actual[j]).unwrap();
+            j += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, panic, process};
+
+    use super::{assert_snapshot_in, line_diff};
+
+    #[test]
+    fn diff_marks_removed_and_added_lines() {
+        let diff = line_diff("a\nb\nc\n", "a\nB\nc\nd\n");
+        assert_eq!(diff, "  a|\n- b|\n+ B|\n  c|\n+ d|\n");
+    }
+
+    #[test]
+    fn diff_keeps_common_lines_in_order() {
+        let diff = line_diff("x\na\nb\n", "a\nb\ny\n");
+        assert_eq!(diff, "- x|\n  a|\n  b|\n+ y|\n");
+    }
+
+    #[test]
+    fn diff_shows_trailing_whitespace() {
+        let diff = line_diff("a \n", "a\n");
+        assert_eq!(diff, "- a |\n+ a|\n");
+    }
+
+    #[test]
+    fn mismatched_snapshots_panic_with_a_diff() {
+        let dir = std::env::temp_dir().join(format!("elm-ui-snapshots-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("list.snap"), "one\ntwo\n").unwrap();
+
+        assert_snapshot_in(&dir, "list", "one\ntwo\n");
+        let err =
+            panic::catch_unwind(|| assert_snapshot_in(&dir, "list", "one\nthree\n")).unwrap_err();
+        let message = err.downcast_ref::<String>().unwrap();
+        assert!(message.starts_with("snapshot 'list' doesn't match"));
+        assert!(message.ends_with("  one|\n- two|\n+ three|\n"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "tui")]
+    #[test]
+    fn styled_snapshots_list_style_runs() {
+        use ratatui::{
+            buffer::Buffer,
+            layout::Rect,
+            style::{Color, Modifier, Style},
+        };
+
+        use super::{Snapshot, Styled};
+
+        let mut buffer = Buffer::empty(Rect::new(0, 0, 6, 2));
+        buffer.set_string(0, 0, "ok", Style::new().fg(Color::Green));
+        buffer.set_string(
+            2,
+            0,
+            "err",
+            Style::new()
+                .underline_color(Color::Red)
+                .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+        );
+        buffer.set_string(0, 1, "plain", Style::new());
+
+        assert_eq!(
+            Styled(&buffer).snapshot(),
+            "okerr \n  ~ 0..2 fg=Green\n  ~ 2..5 ul=Red mod=BOLD|UNDERLINED\nplain \n"
+        );
+        assert_eq!(buffer.snapshot(), "okerr \nplain \n");
+    }
+}
@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+use elm_ui::{Message, Model, Program, ProgramError, ProgramExit, QuitBehavior};
+use futures::FutureExt;
+#[cfg(feature = "tui")]
+use ratatui::{Terminal, backend::TestBackend, buffer::Buffer};
+use tokio::{
+    runtime::{self, Runtime},
+    task,
+    time::{self, Instant},
+};
+
+/// How many times the runtime is yielded to without receiving a message before the program is
+/// considered idle. See [`VirtualUiTester::with_idle_rounds`].
+const IDLE_ROUNDS: usize = 8;
+
+type GetOutput<W, O> = Box<dyn FnMut(&mut W) -> O>;
+
+/// Tests a program on paused tokio time, so timers only fire when the test advances the clock.
+///
+/// Unlike [`UiTester`](crate::UiTester), the program runs on the calling thread, on a
+/// current-thread runtime owned by the tester. Messages are only processed while one of its
+/// methods is running. Blocking commands still run on real threads, so they may finish after
+/// a method returns.
+pub struct VirtualUiTester<M: Model, O> {
+    runtime: Runtime,
+    program: Program<M>,
+    quit: bool,
+    idle_rounds: usize,
+    writer: M::Writer,
+    get_output: GetOutput<M::Writer, O>,
+    output: O,
+}
+
+#[cfg(feature = "tui")]
+impl<M> VirtualUiTester<M, Buffer>
+where
+    M: Model<Writer = Terminal<TestBackend>>,
+{
+    pub fn new_tui(model: M, flags: M::Flags, writer: M::Writer) -> Result<Self, ProgramError<M>> {
+        Self::new(model, flags, writer, |o| o.backend().buffer().clone())
+    }
+}
+
+impl<M: Model, O> VirtualUiTester<M, O> {
+    /// Starts the program and processes messages until it's idle.
+    pub fn new(
+        model: M,
+        flags: M::Flags,
+        mut writer: M::Writer,
+        mut get_output: impl FnMut(&mut M::Writer) -> O + 'static,
+    ) -> Result<Self, ProgramError<M>> {
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .map_err(ProgramError::RuntimeFailure)?;
+        let mut program = Program::builder(model, flags)
+            .with_spawn_event_handler(false)
+            .with_runtime(runtime.handle().clone())
+            .build();
+        runtime.block_on(program.initialize())?;
+        program
+            .view(&mut writer)
+            .map_err(ProgramError::ApplicationFailure)?;
+        let output = get_output(&mut writer);
+        let mut tester = Self {
+            runtime,
+            program,
+            quit: false,
+            idle_rounds: IDLE_ROUNDS,
+            writer,
+            get_output: Box::new(get_output),
+            output,
+        };
+        tester.run_until_idle()?;
+        Ok(tester)
+    }
+
+    /// The output captured after the last render.
+    pub fn output(&self) -> &O {
+        &self.output
+    }
+
+    /// Whether the program has quit.
+    pub fn is_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Sends a message and processes messages until the program is idle.
+    pub fn send_msg(&mut self, msg: Message) -> Result<(), ProgramError<M>> {
+        if !self.quit {
+            self.program.enqueue_msg(msg);
+        }
+        self.run_until_idle()
+    }
+
+    #[cfg(feature = "crossterm")]
+    pub fn send_key(
+        &mut self,
+        key_event: crossterm::event::KeyEvent,
+    ) -> Result<(), ProgramError<M>> {
+        self.send_msg(Message::TermEvent(crossterm::event::Event::Key(key_event)))
+    }
+
+    /// Sets how many times the runtime is yielded to without a message arriving before the
+    /// program is considered idle. Defaults to 8.
+    ///
+    /// Idleness is a heuristic: tokio can't tell whether a spawned task will send a message
+    /// after a few more polls, so a command that awaits many times without touching a timer
+    /// before it returns a message may be picked up by a later call instead. Raise this if a
+    /// test sees such messages arrive late.
+    pub fn with_idle_rounds(mut self, rounds: usize) -> Self {
+        self.idle_rounds = rounds;
+        self
+    }
+
+    /// Moves the clock forward by `duration`, processing messages as they arrive. The clock
+    /// jumps straight to each pending timer deadline, so timers fire in order without the test
+    /// stepping through the time in between.
+    pub fn advance(&mut self, duration: Duration) -> Result<(), ProgramError<M>> {
+        if self.quit {
+            return Ok(());
+        }
+        let deadline = self.runtime.block_on(async { Instant::now() + duration });
+        let Self {
+            runtime,
+            program,
+            writer,
+            get_output,
+            output,
+            ..
+        } = self;
+        self.quit = runtime.block_on(async {
+            let sleep = time::sleep_until(deadline);
+            tokio::pin!(sleep);
+            loop {
+                // While every task is waiting, the paused clock skips ahead to the next timer
+                tokio::select! {
+                    biased;
+                    msg = program.recv_msg() => {
+                        if process(program, writer, get_output, output, msg?).await? {
+                            return Ok(true);
+                        }
+                    }
+                    () = &mut sleep => return Ok::<_, ProgramError<M>>(false),
+                }
+            }
+        })?;
+        // Timers due at the deadline may not have sent their messages yet
+        self.run_until_idle()
+    }
+
+    /// Processes messages until none arrive without the clock moving forward.
+    pub fn run_until_idle(&mut self) -> Result<(), ProgramError<M>> {
+        if self.quit {
+            return Ok(());
+        }
+        let Self {
+            runtime,
+            program,
+            idle_rounds,
+            writer,
+            get_output,
+            output,
+            ..
+        } = self;
+        self.quit = runtime.block_on(async {
+            let mut rounds = 0;
+            while rounds < *idle_rounds {
+                let Some(msg) = program.recv_msg().now_or_never() else {
+                    rounds += 1;
+                    task::yield_now().await;
+                    continue;
+                };
+                rounds = 0;
+                if process(program, writer, get_output, output, msg?).await? {
+                    return Ok(true);
+                }
+            }
+            Ok::<_, ProgramError<M>>(false)
+        })?;
+        Ok(())
+    }
+
+    /// Shuts the program down and returns how it exited.
+    pub fn finish(self) -> Result<(ProgramExit<M>, O), ProgramError<M>> {
+        let exit = self.runtime.block_on(self.program.shutdown())?;
+        if exit.report.has_failures() {
+            return Err(ProgramError::ShutdownFailure(exit.report));
+        }
+        Ok((exit, self.output))
+    }
+}
+
+/// Updates and renders the program with a received message. Returns whether the program quit.
+async fn process<M: Model, O>(
+    program: &mut Program<M>,
+    writer: &mut M::Writer,
+    get_output: &mut GetOutput<M::Writer, O>,
+    output: &mut O,
+    msg: Option<Message>,
+) -> Result<bool, ProgramError<M>> {
+    let Some(msg) = msg else {
+        return Ok(true);
+    };
+    let quit_behavior = program.update(msg).await?;
+    program
+        .view(writer)
+        .map_err(ProgramError::ApplicationFailure)?;
+    *output = get_output(writer);
+    Ok(quit_behavior == QuitBehavior::Quit)
+}
@@ -0,0 +1,98 @@
+use std::{io, time::Duration};
+
+use elm_ui::{Command, Message, Model, OptionalCommand};
+use elm_ui_tester::VirtualUiTester;
+
+struct Tick;
+
+/// Counts timer ticks and quits after `limit` of them. The writer holds the rendered count.
+#[derive(Debug)]
+struct Ticker {
+    interval: Duration,
+    ticks: u32,
+    limit: u32,
+}
+
+impl Ticker {
+    fn new(interval: Duration, limit: u32) -> Self {
+        Self {
+            interval,
+            ticks: 0,
+            limit,
+        }
+    }
+
+    fn schedule(&self) -> OptionalCommand {
+        let interval = self.interval;
+        Some(Command::new_async(move |_, _| async move {
+            tokio::time::sleep(interval).await;
+            Some(Message::custom(Tick))
+        }))
+    }
+}
+
+impl Model for Ticker {
+    type Writer = u32;
+    type Error = io::Error;
+    type Flags = ();
+
+    fn init(&mut self, _flags: Self::Flags) -> Result<OptionalCommand, Self::Error> {
+        Ok(self.schedule())
+    }
+
+    fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error> {
+        if let Message::Custom(msg) = &msg
+            && msg.is::<Tick>()
+        {
+            self.ticks += 1;
+            if self.ticks == self.limit {
+                return Ok(Some(Command::quit()));
+            }
+            return Ok(self.schedule());
+        }
+        Ok(None)
+    }
+
+    fn view(&self, ticks: &mut Self::Writer) -> Result<(), Self::Error> {
+        *ticks = self.ticks;
+        Ok(())
+    }
+}
+
+fn tester(interval: Duration, limit: u32) -> VirtualUiTester<Ticker, u32> {
+    VirtualUiTester::new(Ticker::new(interval, limit), (), 0, |ticks| *ticks).unwrap()
+}
+
+#[test]
+fn timers_fire_at_their_deadline() {
+    let mut tester = tester(Duration::from_millis(500), u32::MAX);
+    assert_eq!(*tester.output(), 0);
+
+    tester.advance(Duration::from_millis(499)).unwrap();
+    assert_eq!(*tester.output(), 0);
+    tester.advance(Duration::from_millis(1)).unwrap();
+    assert_eq!(*tester.output(), 1);
+    tester.advance(Duration::from_millis(1000)).unwrap();
+    assert_eq!(*tester.output(), 3);
+    assert!(!tester.is_quit());
+}
+
+#[test]
+fn advancing_skips_to_each_deadline() {
+    let mut tester = tester(Duration::from_secs(1), u32::MAX);
+    tester.advance(Duration::from_secs(600)).unwrap();
+    assert_eq!(*tester.output(), 600);
+}
+
+#[test]
+fn advancing_stops_when_the_program_quits() {
+    let mut tester = tester(Duration::from_secs(1), 3);
+    tester.advance(Duration::from_secs(10)).unwrap();
+    assert!(tester.is_quit());
+    assert_eq!(*tester.output(), 3);
+    tester.advance(Duration::from_secs(10)).unwrap();
+
+    let (exit, ticks) = tester.finish().unwrap();
+    assert_eq!(ticks, 3);
+    assert_eq!(exit.code, 0);
+}
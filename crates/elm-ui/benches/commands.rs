@@ -0,0 +1,165 @@
+use std::io;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use elm_ui::{Command, Message, Model, OptionalCommand, Program};
+use tokio::runtime::Runtime;
+
+const COMMAND_COUNT: usize = 1_000;
+
+struct Done;
+
+#[derive(Clone, Copy, Debug)]
+enum Names {
+    Unnamed,
+    Shared,
+    Unique,
+}
+
+impl Names {
+    fn name(&self, i: usize) -> String {
+        match self {
+            Self::Unnamed => String::new(),
+            Self::Shared => format!("cmd-{}", i % 8),
+            Self::Unique => format!("cmd-{i}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Spawn {
+    names: Names,
+    remaining: usize,
+}
+
+impl Model for Spawn {
+    type Writer = ();
+    type Error = io::Error;
+    type Flags = ();
+
+    fn init(&mut self, _flags: Self::Flags) -> Result<OptionalCommand, Self::Error> {
+        let cmds = (0..self.remaining)
+            .map(|i| {
+                Command::new_async(|_, _| async { Some(Message::custom(Done)) })
+                    .with_name(self.names.name(i))
+            })
+            .collect();
+        Ok(Some(Command::simple(Message::Batch(cmds))))
+    }
+
+    fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error> {
+        if let Message::Custom(msg) = &msg
+            && msg.is::<Done>()
+        {
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                return Ok(Some(Command::quit()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn view(&self, _writer: &mut Self::Writer) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Cancel {
+    names: Names,
+    started: usize,
+    remaining: usize,
+}
+
+impl Model for Cancel {
+    type Writer = ();
+    type Error = io::Error;
+    type Flags = ();
+
+    fn init(&mut self, _flags: Self::Flags) -> Result<OptionalCommand, Self::Error> {
+        let cmds = (0..self.remaining)
+            .map(|i| {
+                Command::new_async_with_progress(|_, cancellation_token, mut progress| async move {
+                    // Signal that the command has acquired its token
+                    progress.flush();
+                    cancellation_token.cancelled().await;
+                    Some(Message::custom(Done))
+                })
+                .with_name(self.names.name(i))
+            })
+            .collect();
+        Ok(Some(Command::simple(Message::Batch(cmds))))
+    }
+
+    fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error> {
+        match &msg {
+            Message::Progress(_) => {
+                self.started += 1;
+                if self.started == self.remaining {
+                    return Ok(Some(Command::simple(Message::CancelAll)));
+                }
+            }
+            Message::Custom(msg) if msg.is::<Done>() => {
+                self.remaining -= 1;
+                if self.remaining == 0 {
+                    return Ok(Some(Command::quit()));
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn view(&self, _writer: &mut Self::Writer) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+fn commands(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("commands");
+    group.throughput(Throughput::Elements(COMMAND_COUNT as u64));
+    for names in [Names::Unnamed, Names::Shared, Names::Unique] {
+        group.bench_with_input(
+            BenchmarkId::new("spawn", format!("{names:?}")),
+            &names,
+            |b, &names| {
+                b.to_async(&rt).iter(|| async move {
+                    Program::new(
+                        Spawn {
+                            names,
+                            remaining: COMMAND_COUNT,
+                        },
+                        (),
+                    )
+                    .run(&mut ())
+                    .await
+                    .unwrap();
+                });
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("cancel", format!("{names:?}")),
+            &names,
+            |b, &names| {
+                b.to_async(&rt).iter(|| async move {
+                    Program::new(
+                        Cancel {
+                            names,
+                            started: 0,
+                            remaining: COMMAND_COUNT,
+                        },
+                        (),
+                    )
+                    .run(&mut ())
+                    .await
+                    .unwrap();
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, commands);
+criterion_main!(benches);
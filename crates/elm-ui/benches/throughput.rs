@@ -0,0 +1,119 @@
+use std::io;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use elm_ui::{Command, Message, Model, OptionalCommand, Program};
+use tokio::runtime::Runtime;
+
+const MESSAGE_COUNTS: [usize; 2] = [1_000, 10_000];
+
+struct KeyPress;
+
+struct Echo;
+
+#[derive(Debug)]
+struct App {
+    remaining: usize,
+    echo: bool,
+}
+
+impl Model for App {
+    type Writer = ();
+    type Error = io::Error;
+    type Flags = ();
+
+    fn init(&mut self, _flags: Self::Flags) -> Result<OptionalCommand, Self::Error> {
+        if self.echo {
+            return Ok(Some(Command::simple(Message::custom(Echo))));
+        }
+        Ok(None)
+    }
+
+    fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error> {
+        if let Message::Custom(msg) = &msg
+            && (msg.is::<KeyPress>() || msg.is::<Echo>())
+        {
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                return Ok(Some(Command::quit()));
+            }
+            if self.echo {
+                return Ok(Some(Command::simple(Message::custom(Echo))));
+            }
+        }
+        Ok(None)
+    }
+
+    fn view(&self, _writer: &mut Self::Writer) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+fn keypresses(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("keypresses");
+    for count in MESSAGE_COUNTS {
+        group.throughput(Throughput::Elements(count as u64));
+
+        group.bench_with_input(BenchmarkId::new("cmd_tx", count), &count, |b, &count| {
+            b.to_async(&rt).iter(|| async move {
+                let program = Program::new(
+                    App {
+                        remaining: count,
+                        echo: false,
+                    },
+                    (),
+                );
+                let cmd_tx = program.cmd_tx();
+                tokio::spawn(async move {
+                    for _ in 0..count {
+                        cmd_tx
+                            .send(Command::simple(Message::custom(KeyPress)))
+                            .await
+                            .unwrap();
+                    }
+                });
+                program.run(&mut ()).await.unwrap();
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("msg_tx", count), &count, |b, &count| {
+            b.to_async(&rt).iter(|| async move {
+                let program = Program::new(
+                    App {
+                        remaining: count,
+                        echo: false,
+                    },
+                    (),
+                );
+                let msg_tx = program.msg_tx();
+                tokio::spawn(async move {
+                    for _ in 0..count {
+                        msg_tx.send(Message::custom(KeyPress)).await.unwrap();
+                    }
+                });
+                program.run(&mut ()).await.unwrap();
+            });
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("update_simple_cmd", count),
+            &count,
+            |b, &count| {
+                b.to_async(&rt).iter(|| async move {
+                    let program = Program::new(
+                        App {
+                            remaining: count,
+                            echo: true,
+                        },
+                        (),
+                    );
+                    program.run(&mut ()).await.unwrap();
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, keypresses);
+criterion_main!(benches);
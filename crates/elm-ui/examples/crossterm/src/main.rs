@@ -3,7 +3,7 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use elm_ui::{Command, Message, Model, OptionalCommand, Program};
+use elm_ui::{Command, Message, Model, OptionalCommand, Program, TypedMessage};
 use ratatui::{
     backend::CrosstermBackend,
     style::{Color, Style},
@@ -13,7 +13,6 @@ use ratatui::{
 use std::{
     error::Error,
     io::{self},
-    rc::Rc,
 };
 
 #[tokio::main]
@@ -24,7 +23,10 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let program = Program::new(App::default());
+    let program = Program::new(
+        App::default(),
+        vec!["first item".to_owned(), "second_item".to_owned()],
+    );
     program.run(&mut terminal).await?;
 
     disable_raw_mode()?;
@@ -37,7 +39,8 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Debug, TypedMessage)]
+#[typed_message(debug)]
 pub enum AppMessage {
     SetListItems(Vec<String>),
 }
@@ -67,35 +70,33 @@ impl App {
 impl Model for App {
     type Writer = Terminal<CrosstermBackend<io::Stdout>>;
     type Error = io::Error;
+    type Flags = Vec<String>;
 
-    fn init(&mut self) -> Result<OptionalCommand, Self::Error> {
-        Ok(Some(Command::simple(Message::Custom(Box::new(
-            AppMessage::SetListItems(vec!["first item".to_owned(), "second_item".to_owned()]),
-        )))))
+    fn init(&mut self, list_items: Self::Flags) -> Result<OptionalCommand, Self::Error> {
+        Ok(Some(Command::simple(AppMessage::SetListItems(list_items).into())))
     }
 
-    fn update(&mut self, msg: Rc<Message>) -> Result<OptionalCommand, Self::Error> {
-        match msg.as_ref() {
-            Message::Custom(msg) => {
-                if let Some(AppMessage::SetListItems(items)) = msg.downcast_ref::<AppMessage>() {
-                    self.list_items = items.clone();
-                    if self.list_items.is_empty() {
-                        self.list_index = None;
-                    } else {
-                        self.list_index = Some(0);
-                        self.list_state.select(self.list_index);
-                    }
+    fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error> {
+        // Messages that aren't an AppMessage are handed back unchanged
+        match AppMessage::from_message(msg) {
+            Ok(AppMessage::SetListItems(items)) => {
+                self.list_items = items;
+                if self.list_items.is_empty() {
+                    self.list_index = None;
+                } else {
+                    self.list_index = Some(0);
+                    self.list_state.select(self.list_index);
                 }
             }
-            Message::TermEvent(Event::Key(KeyEvent {
+            Err(Message::TermEvent(Event::Key(KeyEvent {
                 code: KeyCode::Char('q' | 'Q'),
                 ..
-            })) => {
-                return Ok(Some(Command::simple(Message::Quit)));
+            }))) => {
+                return Ok(Some(Command::quit()));
             }
-            Message::TermEvent(Event::Key(KeyEvent {
+            Err(Message::TermEvent(Event::Key(KeyEvent {
                 code: KeyCode::Up, ..
-            })) => {
+            }))) => {
                 if let Some(list_index) = self.list_index.as_mut() {
                     if *list_index > 0 {
                         *list_index -= 1;
@@ -103,10 +104,10 @@ impl Model for App {
                     }
                 }
             }
-            Message::TermEvent(Event::Key(KeyEvent {
+            Err(Message::TermEvent(Event::Key(KeyEvent {
                 code: KeyCode::Down,
                 ..
-            })) => {
+            }))) => {
                 if let Some(list_index) = self.list_index.as_mut() {
                     if *list_index < self.list_items.len() - 1 {
                         *list_index += 1;
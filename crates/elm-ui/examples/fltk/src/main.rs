@@ -1,6 +1,8 @@
-use std::{io, rc::Rc, time::Duration};
+use std::{io, time::Duration};
 
-use elm_ui::{Command, Message, Model, OptionalCommand, Program};
+use elm_ui::{
+    Command, Message, Model, OptionalCommand, Program, QuitBehavior, QuitRequest, TypedMessage,
+};
 use fltk::{
     app,
     button::Button,
@@ -10,6 +12,9 @@ use fltk::{
     window::Window,
 };
 
+/// How often the program processes pending messages, in seconds.
+const STEP_INTERVAL: f64 = 0.01;
+
 fn main() {
     let app = app::App::default();
     let mut wind = Window::default().with_size(400, 300);
@@ -31,21 +36,31 @@ fn main() {
     wind.end();
     wind.show();
 
-    let program = Program::new(App { val: 0 });
-    let cmd_tx = program.cmd_tx();
+    let program = Program::new(App { val: 0 }, ());
+    let handle = program.handle();
     but_inc.set_callback({
-        let cmd_tx = cmd_tx.clone();
+        let handle = handle.clone();
         move |_| {
-            cmd_tx
-                .try_send(Command::simple(Message::custom(AppMessage::Increment)))
-                .unwrap();
+            if let Err(e) = handle.try_send(AppMessage::Increment.into()) {
+                eprintln!("Failed to send message: {e}");
+            }
         }
     });
 
-    but_dec.set_callback(move |_| {
-        cmd_tx
-            .try_send(Command::simple(Message::custom(AppMessage::Decrement)))
-            .unwrap();
+    but_dec.set_callback({
+        let handle = handle.clone();
+        move |_| {
+            if let Err(e) = handle.try_send(AppMessage::Decrement.into()) {
+                eprintln!("Failed to send message: {e}");
+            }
+        }
+    });
+
+    // Closing the window asks the program to quit, and the window closes once it has shut down
+    wind.set_callback(move |_| {
+        if let Err(e) = handle.try_quit(QuitRequest::new()) {
+            eprintln!("Failed to send quit request: {e}");
+        }
     });
     but_inc.set_color(Color::from_u32(0x304FFE));
     but_inc.set_selection_color(Color::Green);
@@ -58,17 +73,34 @@ fn main() {
     but_dec.set_label_size(20);
     but_dec.set_label_color(Color::White);
 
-    std::thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(async move {
-            program.run(&mut frame).await.unwrap();
-        });
+    let mut program = Some(program);
+    app::add_timeout3(STEP_INTERVAL, move |timeout| {
+        let Some(running) = program.as_mut() else {
+            return;
+        };
+        match running.step(&mut frame) {
+            Ok(QuitBehavior::Continue) => {
+                app::repeat_timeout3(STEP_INTERVAL, timeout);
+                return;
+            }
+            Ok(QuitBehavior::Quit) => {}
+            Err(e) => eprintln!("Program failed: {e}"),
+        }
+        if let Some(program) = program.take() {
+            match program.shutdown_blocking() {
+                Ok(exit) if !exit.report.is_clean() => eprintln!("{}", exit.report),
+                Ok(_) => {}
+                Err(e) => eprintln!("Failed to shut down: {e}"),
+            }
+        }
+        app.quit();
     });
 
     app.run().unwrap();
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, TypedMessage)]
+#[typed_message(debug)]
 pub enum AppMessage {
     AutoIncrement,
     Increment,
@@ -83,33 +115,31 @@ pub struct App {
 impl Model for App {
     type Writer = Frame;
     type Error = io::Error;
+    type Flags = ();
 
-    fn init(&mut self) -> Result<OptionalCommand, Self::Error> {
+    fn init(&mut self, _flags: Self::Flags) -> Result<OptionalCommand, Self::Error> {
         Ok(Some(Command::new_async(|_, _| async move {
             tokio::time::sleep(Duration::from_millis(500)).await;
-            Some(Message::custom(AppMessage::AutoIncrement))
+            Some(AppMessage::AutoIncrement.into())
         })))
     }
 
-    fn update(&mut self, msg: Rc<Message>) -> Result<OptionalCommand, Self::Error> {
-        if let Message::Custom(custom_msg) = msg.as_ref() {
-            if let Some(msg) = custom_msg.downcast_ref::<AppMessage>() {
-                match msg {
-                    AppMessage::AutoIncrement => {
-                        self.val += 1;
-                        return Ok(Some(Command::new_async(move |_, _| async move {
-                            tokio::time::sleep(Duration::from_millis(500)).await;
-                            Some(Message::custom(AppMessage::AutoIncrement))
-                        })));
-                    }
-                    AppMessage::Increment => {
-                        self.val += 5;
-                    }
-                    AppMessage::Decrement => {
-                        self.val -= 5;
-                    }
-                }
+    fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error> {
+        match AppMessage::from_message(msg) {
+            Ok(AppMessage::AutoIncrement) => {
+                self.val += 1;
+                return Ok(Some(Command::new_async(move |_, _| async move {
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    Some(AppMessage::AutoIncrement.into())
+                })));
+            }
+            Ok(AppMessage::Increment) => {
+                self.val += 5;
+            }
+            Ok(AppMessage::Decrement) => {
+                self.val -= 5;
             }
+            Err(_) => {}
         }
         Ok(None)
     }
@@ -1,18 +1,20 @@
 use std::{
     error::Error,
     io::{self, Write},
-    rc::Rc,
     time::Duration,
 };
 
-use elm_ui::{Command, Message, Model, OptionalCommand, Program};
+use elm_ui::{Command, Message, Model, OptionalCommand, Program, TypedMessage};
 
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn Error>> {
-    Program::new(App::default()).run(&mut io::stdout()).await?;
+    Program::new(App::default(), ())
+        .run(&mut io::stdout())
+        .await?;
     Ok(())
 }
 
+#[derive(TypedMessage)]
 struct TickMsg(usize);
 
 #[derive(Default, Debug)]
@@ -26,28 +28,27 @@ impl Model for App {
 
     type Error = io::Error;
 
-    fn init(&mut self) -> Result<OptionalCommand, Self::Error> {
+    type Flags = ();
+
+    fn init(&mut self, _flags: Self::Flags) -> Result<OptionalCommand, Self::Error> {
         Ok(Some(Command::new_async(|_, _| async move {
             tokio::time::sleep(Duration::from_millis(500)).await;
-            Some(Message::custom(TickMsg(1)))
+            Some(TickMsg(1).into())
         })))
     }
 
-    fn update(&mut self, msg: Rc<Message>) -> Result<OptionalCommand, Self::Error> {
-        if let Message::Custom(custom_msg) = msg.as_ref() {
-            if let Some(TickMsg(seq_num)) = custom_msg.downcast_ref() {
-                let seq_num = *seq_num;
-                self.seq_num = seq_num;
-                if seq_num > 5 {
-                    self.quitting = true;
-                    return Ok(Some(Command::quit()));
-                }
-
-                return Ok(Some(Command::new_async(move |_, _| async move {
-                    tokio::time::sleep(Duration::from_millis(500)).await;
-                    Some(Message::custom(TickMsg(seq_num + 1)))
-                })));
+    fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error> {
+        if let Ok(TickMsg(seq_num)) = TickMsg::from_message(msg) {
+            self.seq_num = seq_num;
+            if seq_num > 5 {
+                self.quitting = true;
+                return Ok(Some(Command::quit()));
             }
+
+            return Ok(Some(Command::new_async(move |_, _| async move {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                Some(TickMsg(seq_num + 1).into())
+            })));
         }
         Ok(None)
     }
@@ -1,4 +1,4 @@
-use elm_ui::{Command, Message, Model, OptionalCommand, Program};
+use elm_ui::{Command, Message, Model, OptionalCommand, Program, TypedMessage};
 use ratatui::{
     backend::{Backend, TermionBackend},
     style::{Color, Style},
@@ -8,7 +8,6 @@ use ratatui::{
 use std::{
     error::Error,
     io::{self, Stdout},
-    rc::Rc,
 };
 use termion::{
     event::Key,
@@ -25,9 +24,9 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
     let backend = TermionBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let program = Program::new(App::default());
-    let cmd_tx = program.cmd_tx();
-    spawn_event_reader(cmd_tx);
+    let program = Program::new(App::default(), ());
+    let msg_tx = program.msg_tx();
+    spawn_event_reader(msg_tx);
     program.run(&mut terminal).await?;
 
     terminal.backend_mut().flush()?;
@@ -37,7 +36,8 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Debug, TypedMessage)]
+#[typed_message(debug)]
 pub enum AppMessage {
     SetListItems(Vec<String>),
     KeyEvent(Key),
@@ -50,14 +50,12 @@ pub struct App {
     list_state: ListState,
 }
 
-fn spawn_event_reader(cmd_tx: mpsc::Sender<Command>) {
+fn spawn_event_reader(msg_tx: mpsc::Sender<Message>) {
     task::spawn_blocking(move || {
         let stdin = std::io::stdin();
         for event in stdin.keys().flatten() {
-            cmd_tx
-                .blocking_send(Command::simple(Message::custom(AppMessage::KeyEvent(
-                    event,
-                ))))
+            msg_tx
+                .blocking_send(AppMessage::KeyEvent(event).into())
                 .unwrap();
             if matches!(event, Key::Char('q')) {
                 // Need to ensure we drop the stdin reference cleanly here or the terminal state won't be restored properly
@@ -70,48 +68,46 @@ fn spawn_event_reader(cmd_tx: mpsc::Sender<Command>) {
 impl Model for App {
     type Writer = Terminal<TermionBackend<AlternateScreen<RawTerminal<Stdout>>>>;
     type Error = io::Error;
+    type Flags = ();
 
-    fn init(&mut self) -> Result<OptionalCommand, Self::Error> {
-        Ok(Some(Command::simple(Message::Custom(Box::new(
-            AppMessage::SetListItems(vec!["first item".to_owned(), "second_item".to_owned()]),
-        )))))
+    fn init(&mut self, _flags: Self::Flags) -> Result<OptionalCommand, Self::Error> {
+        Ok(Some(Command::simple(
+            AppMessage::SetListItems(vec!["first item".to_owned(), "second_item".to_owned()])
+                .into(),
+        )))
     }
 
-    fn update(&mut self, msg: Rc<Message>) -> Result<OptionalCommand, Self::Error> {
-        if let Message::Custom(msg) = msg.as_ref() {
-            if let Some(msg) = msg.downcast_ref::<AppMessage>() {
-                match msg {
-                    AppMessage::SetListItems(items) => {
-                        self.list_items = items.clone();
-                        if self.list_items.is_empty() {
-                            self.list_index = None;
-                        } else {
-                            self.list_index = Some(0);
-                            self.list_state.select(self.list_index);
-                        }
-                    }
-                    AppMessage::KeyEvent(Key::Char('q')) => {
-                        return Ok(Some(Command::simple(Message::Quit)));
-                    }
-                    AppMessage::KeyEvent(Key::Up) => {
-                        if let Some(list_index) = self.list_index.as_mut() {
-                            if *list_index > 0 {
-                                *list_index -= 1;
-                                self.list_state.select(Some(*list_index));
-                            }
-                        }
+    fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error> {
+        match AppMessage::from_message(msg) {
+            Ok(AppMessage::SetListItems(items)) => {
+                self.list_items = items;
+                if self.list_items.is_empty() {
+                    self.list_index = None;
+                } else {
+                    self.list_index = Some(0);
+                    self.list_state.select(self.list_index);
+                }
+            }
+            Ok(AppMessage::KeyEvent(Key::Char('q'))) => {
+                return Ok(Some(Command::quit()));
+            }
+            Ok(AppMessage::KeyEvent(Key::Up)) => {
+                if let Some(list_index) = self.list_index.as_mut() {
+                    if *list_index > 0 {
+                        *list_index -= 1;
+                        self.list_state.select(Some(*list_index));
                     }
-                    AppMessage::KeyEvent(Key::Down) => {
-                        if let Some(list_index) = self.list_index.as_mut() {
-                            if *list_index < self.list_items.len() - 1 {
-                                *list_index += 1;
-                                self.list_state.select(Some(*list_index));
-                            }
-                        }
+                }
+            }
+            Ok(AppMessage::KeyEvent(Key::Down)) => {
+                if let Some(list_index) = self.list_index.as_mut() {
+                    if *list_index < self.list_items.len() - 1 {
+                        *list_index += 1;
+                        self.list_state.select(Some(*list_index));
                     }
-                    _ => {}
                 }
             }
+            _ => {}
         }
 
         Ok(None)
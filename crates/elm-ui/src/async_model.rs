@@ -0,0 +1,61 @@
+use crate::{Message, Model, OptionalCommand, QuitDecision, QuitRequest};
+
+/// A model whose `init`, `update` and `view` may wait, e.g. to write to an async writer such as
+/// a network socket. Every [`Model`] is also an `AsyncModel`, so both kinds are driven by the
+/// same [`Program`](crate::Program).
+///
+/// The program waits for each call to finish before processing the next message, so these
+/// should only wait for short operations. Long-running work belongs in a
+/// [`Command`](crate::Command).
+pub trait AsyncModel {
+    type Writer;
+    type Error: std::error::Error + ToString;
+    /// Startup configuration passed to [`AsyncModel::init`].
+    type Flags;
+
+    fn init(
+        &mut self,
+        flags: Self::Flags,
+    ) -> impl Future<Output = Result<OptionalCommand, Self::Error>>;
+    fn update(
+        &mut self,
+        msg: Message,
+    ) -> impl Future<Output = Result<OptionalCommand, Self::Error>>;
+    fn view(&self, writer: &mut Self::Writer) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Called when the program is asked to quit, unless the request is forced.
+    fn on_quit(&mut self, _request: &QuitRequest) -> Result<QuitDecision, Self::Error> {
+        Ok(QuitDecision::Allow)
+    }
+
+    /// Called when the program shuts down. See [`Model::on_shutdown`].
+    fn on_shutdown(&mut self) -> Result<OptionalCommand, Self::Error> {
+        Ok(None)
+    }
+}
+
+impl<M: Model> AsyncModel for M {
+    type Writer = <M as Model>::Writer;
+    type Error = <M as Model>::Error;
+    type Flags = <M as Model>::Flags;
+
+    async fn init(&mut self, flags: Self::Flags) -> Result<OptionalCommand, Self::Error> {
+        Model::init(self, flags)
+    }
+
+    async fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error> {
+        Model::update(self, msg)
+    }
+
+    async fn view(&self, writer: &mut Self::Writer) -> Result<(), Self::Error> {
+        Model::view(self, writer)
+    }
+
+    fn on_quit(&mut self, request: &QuitRequest) -> Result<QuitDecision, Self::Error> {
+        Model::on_quit(self, request)
+    }
+
+    fn on_shutdown(&mut self) -> Result<OptionalCommand, Self::Error> {
+        Model::on_shutdown(self)
+    }
+}
@@ -0,0 +1,238 @@
+use std::time::Duration;
+
+use futures::{Stream, StreamExt, stream::BoxStream};
+use tokio::{runtime::Handle, sync::mpsc};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    AsyncModel, ConcurrencyLimits, FrameBudget, Message, Middleware, Program, SharedUpdate,
+    middleware::SharedUpdateFn, queue::MessageQueue,
+};
+
+/// What happens when the model returns an error.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stops the program and returns the error.
+    #[default]
+    Exit,
+    /// Keeps running as if the model hadn't returned anything. Use [`Middleware::on_error`] to
+    /// observe the error.
+    Continue,
+}
+
+/// What happens when a command panics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Stops the program and returns the panic as an error.
+    #[default]
+    Exit,
+    /// Keeps running. The panicked command doesn't produce a message.
+    Continue,
+}
+
+/// Configures a [`Program`].
+pub struct ProgramBuilder<M: AsyncModel> {
+    model: M,
+    flags: M::Flags,
+    command_capacity: usize,
+    message_capacity: usize,
+    frame_budget: FrameBudget,
+    render_interval: Option<Duration>,
+    coalesce_events: bool,
+    #[cfg(feature = "crossterm")]
+    spawn_event_handler: bool,
+    concurrency_limits: ConcurrencyLimits,
+    shutdown_timeout: Option<Duration>,
+    error_policy: ErrorPolicy,
+    panic_policy: PanicPolicy,
+    runtime: Option<Handle>,
+    event_sources: Vec<BoxStream<'static, Message>>,
+    middleware: Vec<Box<dyn Middleware<M> + Send>>,
+    shared_update: Option<SharedUpdateFn<M>>,
+}
+
+impl<M: AsyncModel> ProgramBuilder<M> {
+    pub fn new(model: M, flags: M::Flags) -> Self {
+        Self {
+            model,
+            flags,
+            command_capacity: 32,
+            message_capacity: 32,
+            frame_budget: FrameBudget::default(),
+            render_interval: None,
+            coalesce_events: true,
+            #[cfg(feature = "crossterm")]
+            spawn_event_handler: true,
+            concurrency_limits: ConcurrencyLimits::default(),
+            shutdown_timeout: None,
+            error_policy: ErrorPolicy::default(),
+            panic_policy: PanicPolicy::default(),
+            runtime: None,
+            event_sources: Vec::new(),
+            middleware: Vec::new(),
+            shared_update: None,
+        }
+    }
+
+    /// Sets how many commands may wait to be started before senders have to wait. Defaults
+    /// to 32. A capacity of zero is treated as one.
+    pub fn with_command_capacity(self, command_capacity: usize) -> Self {
+        Self {
+            command_capacity: command_capacity.max(1),
+            ..self
+        }
+    }
+
+    /// Sets how many messages may wait to be processed before senders have to wait. Defaults
+    /// to 32. A capacity of zero is treated as one.
+    pub fn with_message_capacity(self, message_capacity: usize) -> Self {
+        Self {
+            message_capacity: message_capacity.max(1),
+            ..self
+        }
+    }
+
+    pub fn with_frame_budget(self, frame_budget: FrameBudget) -> Self {
+        Self {
+            frame_budget,
+            ..self
+        }
+    }
+
+    /// Limits how many times per second [`Program::run`] renders the view. Messages that
+    /// arrive in between are still processed, and the view is rendered once the interval has
+    /// passed. By default, the view is rendered after every update. A rate of zero is treated
+    /// as one.
+    pub fn with_render_rate(self, frames_per_second: u32) -> Self {
+        Self {
+            render_interval: Some(Duration::from_secs(1) / frames_per_second.max(1)),
+            ..self
+        }
+    }
+
+    /// Whether pending terminal resize and mouse-move events are collapsed into the latest one.
+    /// Enabled by default.
+    pub fn with_event_coalescing(self, coalesce_events: bool) -> Self {
+        Self {
+            coalesce_events,
+            ..self
+        }
+    }
+
+    /// Whether terminal events are read from crossterm. Enabled by default.
+    #[cfg(feature = "crossterm")]
+    pub fn with_spawn_event_handler(self, spawn_event_handler: bool) -> Self {
+        Self {
+            spawn_event_handler,
+            ..self
+        }
+    }
+
+    pub fn with_concurrency_limits(self, concurrency_limits: ConcurrencyLimits) -> Self {
+        Self {
+            concurrency_limits,
+            ..self
+        }
+    }
+
+    /// Limits how long [`Program::shutdown`] waits for running tasks before aborting them. By
+    /// default, it waits indefinitely.
+    pub fn with_shutdown_timeout(self, shutdown_timeout: Duration) -> Self {
+        Self {
+            shutdown_timeout: Some(shutdown_timeout),
+            ..self
+        }
+    }
+
+    pub fn with_error_policy(self, error_policy: ErrorPolicy) -> Self {
+        Self {
+            error_policy,
+            ..self
+        }
+    }
+
+    pub fn with_panic_policy(self, panic_policy: PanicPolicy) -> Self {
+        Self {
+            panic_policy,
+            ..self
+        }
+    }
+
+    /// Runs the program's tasks on the given runtime instead of the one the program is
+    /// started from. Commands are spawned from those tasks, so they use the same runtime.
+    pub fn with_runtime(self, runtime: Handle) -> Self {
+        Self {
+            runtime: Some(runtime),
+            ..self
+        }
+    }
+
+    /// Adds a stream whose messages are sent to the program until it shuts down, like the
+    /// built-in terminal event reader.
+    pub fn with_event_source(
+        mut self,
+        source: impl Stream<Item = Message> + Send + 'static,
+    ) -> Self {
+        self.event_sources.push(source.boxed());
+        self
+    }
+
+    pub fn with_middleware(mut self, middleware: impl Middleware<M> + Send + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Passes messages to [`SharedUpdate::update_shared`] by reference instead of moving them
+    /// into [`Model::update`](crate::Model::update), so that middleware can observe them after
+    /// they've been handled.
+    pub fn with_shared_messages(self) -> Self
+    where
+        M: SharedUpdate,
+    {
+        Self {
+            shared_update: Some(M::update_shared),
+            ..self
+        }
+    }
+
+    pub fn build(self) -> Program<M> {
+        let (cmd_tx, cmd_rx) = mpsc::channel(self.command_capacity);
+        let (msg_tx, msg_rx) = mpsc::channel(self.message_capacity);
+        let (query_tx, query_rx) = mpsc::unbounded_channel();
+        let mut msg_queue = MessageQueue::new(msg_rx);
+        msg_queue.set_prioritize_input(self.frame_budget.prioritizes_input());
+        msg_queue.set_coalesce_events(self.coalesce_events);
+        Program {
+            model: self.model,
+            flags: Some(self.flags),
+            cmd_tx,
+            cmd_rx: Some(cmd_rx),
+            msg_tx,
+            msg_queue,
+            frame_budget: self.frame_budget,
+            render_interval: self.render_interval,
+            #[cfg(feature = "crossterm")]
+            spawn_event_handler: self.spawn_event_handler,
+            concurrency_limits: self.concurrency_limits,
+            error_policy: self.error_policy,
+            panic_policy: self.panic_policy,
+            runtime: self.runtime,
+            event_sources: self.event_sources,
+            middleware: self.middleware,
+            shared_update: self.shared_update,
+            handlers: Default::default(),
+            event_handler_task: None,
+            message_handler_task: None,
+            handler_cancellation_token: CancellationToken::new(),
+            handler_abort_token: CancellationToken::new(),
+            shutdown_timeout: self.shutdown_timeout,
+            registry: Default::default(),
+            quit_request: None,
+            pending_cmds: Default::default(),
+            ready_msgs: Vec::new(),
+            owned_runtime: None,
+            query_tx,
+            query_rx,
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    any::{Any, TypeId, type_name},
+    fmt::{self, Debug},
+};
+
+type DebugFn = fn(&(dyn Any + Send), &mut fmt::Formatter<'_>) -> fmt::Result;
+
+/// An application-defined message payload, along with the name of its type so that it can be
+/// identified in logs and error reports.
+pub struct CustomMessage {
+    value: Box<dyn Any + Send>,
+    type_name: &'static str,
+    debug: Option<DebugFn>,
+}
+
+impl CustomMessage {
+    pub fn new<T: Any + Send>(value: T) -> Self {
+        Self {
+            value: Box::new(value),
+            type_name: type_name::<T>(),
+            debug: None,
+        }
+    }
+
+    /// Like [`CustomMessage::new`], but also uses the value's [`Debug`] implementation when
+    /// the message is formatted.
+    pub fn with_debug<T: Any + Send + Debug>(value: T) -> Self {
+        Self {
+            debug: Some(|value, f| match value.downcast_ref::<T>() {
+                Some(value) => value.fmt(f),
+                None => unreachable!("the formatter is created for the stored type"),
+            }),
+            ..Self::new(value)
+        }
+    }
+
+    /// The name of the payload's type, as returned by [`std::any::type_name`].
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    pub fn type_id(&self) -> TypeId {
+        (*self.value).type_id()
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.value.is::<T>()
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.value.downcast_ref()
+    }
+
+    pub fn downcast_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.value.downcast_mut()
+    }
+
+    /// Takes the payload out if it has type `T`, otherwise returns the message unchanged.
+    pub fn downcast<T: Any>(self) -> Result<T, Self> {
+        let Self {
+            value,
+            type_name,
+            debug,
+        } = self;
+        value.downcast().map(|value| *value).map_err(|value| Self {
+            value,
+            type_name,
+            debug,
+        })
+    }
+}
+
+impl Debug for CustomMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.debug {
+            Some(debug) => debug(&*self.value, f),
+            None => f.debug_struct(self.type_name).finish_non_exhaustive(),
+        }
+    }
+}
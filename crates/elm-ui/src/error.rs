@@ -0,0 +1,208 @@
+use std::fmt::Display;
+
+use tokio::{sync::mpsc::error::SendError, task::JoinError};
+
+use crate::{AsyncModel, Command, CommandFn, Message, ShutdownReport};
+
+/// The kind of work a command performs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CommandKind {
+    Ready,
+    Async,
+    Blocking,
+    Map,
+    Then,
+    Race,
+    Join,
+}
+
+impl Display for CommandKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            Self::Ready => "ready",
+            Self::Async => "async",
+            Self::Blocking => "blocking",
+            Self::Map => "map",
+            Self::Then => "then",
+            Self::Race => "race",
+            Self::Join => "join",
+        };
+        write!(f, "{kind}")
+    }
+}
+
+/// Identifies a command in errors and shutdown reports.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CommandInfo {
+    /// Unique for every command created by the process.
+    pub id: u64,
+    /// Empty for unnamed commands.
+    pub name: String,
+    pub kind: CommandKind,
+}
+
+impl Display for CommandInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "unnamed {} command #{}", self.kind, self.id)
+        } else {
+            write!(f, "{} command '{}' #{}", self.kind, self.name, self.id)
+        }
+    }
+}
+
+/// The channel a send failed on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Message,
+    Command,
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Message => write!(f, "message"),
+            Self::Command => write!(f, "command"),
+        }
+    }
+}
+
+/// Something that can be sent on one of the program's channels.
+pub(crate) trait Unsent {
+    fn describe(&self) -> String;
+}
+
+impl Unsent for Message {
+    fn describe(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+impl Unsent for Command {
+    fn describe(&self) -> String {
+        match &self.func {
+            CommandFn::Ready(msg) => format!("{} returning {msg:?}", self.info()),
+            _ => self.info().to_string(),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MessageError {
+    /// The receiving end of the channel was dropped.
+    #[error("failed to send {unsent} on the {channel} channel")]
+    SendFailure {
+        channel: Channel,
+        /// The message that couldn't be sent, formatted with its [`Debug`] implementation, or
+        /// the command, identified by its [`CommandInfo`].
+        unsent: String,
+        #[source]
+        source: SendError<()>,
+    },
+    /// A task panicked or was aborted.
+    #[error("task {}", if .0.is_panic() { "panicked" } else { "was cancelled" })]
+    JoinFailure(#[source] JoinError),
+    /// A command returned an error or panicked.
+    #[error("{command} failed")]
+    CommandFailure {
+        command: CommandInfo,
+        #[source]
+        source: Box<MessageError>,
+    },
+}
+
+impl MessageError {
+    pub(crate) fn send_failure<T: Unsent>(channel: Channel) -> impl FnOnce(SendError<T>) -> Self {
+        move |SendError(unsent)| Self::SendFailure {
+            channel,
+            unsent: unsent.describe(),
+            source: SendError(()),
+        }
+    }
+
+    /// The command that failed, if the error came from a command.
+    pub fn command(&self) -> Option<&CommandInfo> {
+        match self {
+            Self::CommandFailure { command, .. } => Some(command),
+            _ => None,
+        }
+    }
+
+    /// Whether the error was caused by a panic.
+    pub fn is_panic(&self) -> bool {
+        match self {
+            Self::JoinFailure(e) => e.is_panic(),
+            Self::CommandFailure { source, .. } => source.is_panic(),
+            Self::SendFailure { .. } => false,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProgramError<M: AsyncModel> {
+    #[error(transparent)]
+    MessageFailure(MessageError),
+    #[error("{0}")]
+    ApplicationFailure(M::Error),
+    /// Tasks failed while shutting down. [`Program::run`](crate::Program::run) reports these in
+    /// [`ProgramExit::report`](crate::ProgramExit::report) instead, so this is for callers
+    /// that treat them as errors.
+    #[error("{0}")]
+    ShutdownFailure(ShutdownReport),
+    /// The runtime for [`Program::run_blocking`](crate::Program::run_blocking) couldn't be
+    /// created.
+    #[error("failed to create runtime")]
+    RuntimeFailure(#[source] std::io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::error::SendError;
+
+    use super::{Channel, MessageError};
+    use crate::{Command, Message};
+
+    #[derive(Debug)]
+    struct Tick;
+
+    struct Opaque;
+
+    #[test]
+    fn send_failures_name_the_unsent_message() {
+        let error =
+            MessageError::send_failure(Channel::Message)(SendError(Message::custom_debug(Tick)));
+        assert_eq!(
+            error.to_string(),
+            "failed to send Custom(Tick) on the message channel"
+        );
+
+        let error =
+            MessageError::send_failure(Channel::Message)(SendError(Message::custom(Opaque)));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "failed to send Custom({} {{ .. }}) on the message channel",
+                std::any::type_name::<Opaque>()
+            )
+        );
+    }
+
+    #[test]
+    fn send_failures_identify_the_unsent_command() {
+        let cmd = Command::new_async(|_, _| async { None }).with_name("load");
+        let info = cmd.info();
+        let error = MessageError::send_failure(Channel::Command)(SendError(cmd));
+        assert_eq!(
+            error.to_string(),
+            format!("failed to send {info} on the command channel")
+        );
+
+        let cmd = Command::simple(Message::custom_debug(Tick));
+        let info = cmd.info();
+        let error = MessageError::send_failure(Channel::Command)(SendError(cmd));
+        assert_eq!(
+            error.to_string(),
+            format!("failed to send {info} returning Custom(Tick) on the command channel")
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::{any::Any, fmt::Debug, process::ExitCode};
+
+use crate::{Command, ShutdownReport};
+
+/// A request to quit the program, sent with [`Message::Quit`](crate::Message::Quit).
+///
+/// Unless the request is forced, the model can veto it in
+/// [`Model::on_quit`](crate::Model::on_quit).
+#[derive(Debug, Default)]
+pub struct QuitRequest {
+    code: i32,
+    value: Option<Box<dyn Any + Send>>,
+    force: bool,
+}
+
+impl QuitRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the exit code returned from [`Program::run`](crate::Program::run). Defaults to
+    /// zero.
+    pub fn with_code(self, code: i32) -> Self {
+        Self { code, ..self }
+    }
+
+    /// Attaches a value that's returned from [`Program::run`](crate::Program::run).
+    pub fn with_value(self, value: impl Any + Send) -> Self {
+        Self {
+            value: Some(Box::new(value)),
+            ..self
+        }
+    }
+
+    /// Quits without consulting [`Model::on_quit`](crate::Model::on_quit).
+    pub fn forced(self) -> Self {
+        Self {
+            force: true,
+            ..self
+        }
+    }
+
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    pub fn value<T: Any>(&self) -> Option<&T> {
+        self.value.as_ref()?.downcast_ref()
+    }
+
+    pub fn is_forced(&self) -> bool {
+        self.force
+    }
+}
+
+/// The model's answer to a [`QuitRequest`].
+#[derive(Debug)]
+pub enum QuitDecision {
+    Allow,
+    /// Keeps the program running and runs the command, if any. To quit later, send another
+    /// quit request, forcing it if the model shouldn't be asked again.
+    Veto(Option<Command>),
+}
+
+/// The result of a program that ran to completion.
+#[derive(Debug)]
+pub struct ProgramExit<M> {
+    pub model: M,
+    /// The exit code from the [`QuitRequest`], or zero if the program stopped for any other
+    /// reason.
+    pub code: i32,
+    pub value: Option<Box<dyn Any + Send>>,
+    pub report: ShutdownReport,
+}
+
+impl<M> ProgramExit<M> {
+    pub(crate) fn new(model: M, request: Option<QuitRequest>, report: ShutdownReport) -> Self {
+        let QuitRequest { code, value, .. } = request.unwrap_or_default();
+        Self {
+            model,
+            code,
+            value,
+            report,
+        }
+    }
+
+    /// Removes the exit value if it has the type `T`.
+    pub fn take_value<T: Any>(&mut self) -> Option<T> {
+        match self.value.take()?.downcast() {
+            Ok(value) => Some(*value),
+            Err(value) => {
+                self.value = Some(value);
+                None
+            }
+        }
+    }
+
+    /// The exit code as a process exit status. Codes outside of `0..=255` are reported as a
+    /// generic failure.
+    pub fn exit_code(&self) -> ExitCode {
+        match u8::try_from(self.code) {
+            Ok(code) => ExitCode::from(code),
+            Err(_) => ExitCode::FAILURE,
+        }
+    }
+}
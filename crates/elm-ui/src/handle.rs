@@ -0,0 +1,101 @@
+use tokio::sync::{
+    mpsc::{
+        self,
+        error::{SendError, TrySendError},
+    },
+    oneshot,
+};
+
+use crate::{Message, QuitRequest};
+
+pub(crate) type QueryFn<M> = Box<dyn FnOnce(&M) + Send>;
+
+/// The program stopped before answering a query.
+#[derive(thiserror::Error, Debug)]
+#[error("the program stopped before answering the query")]
+pub struct QueryError;
+
+/// A cloneable handle for interacting with a running [`Program`](crate::Program) from other
+/// tasks and threads, e.g. from GUI callbacks or signal handlers.
+pub struct ProgramHandle<M> {
+    msg_tx: mpsc::Sender<Message>,
+    query_tx: mpsc::UnboundedSender<QueryFn<M>>,
+}
+
+impl<M> Clone for ProgramHandle<M> {
+    fn clone(&self) -> Self {
+        Self {
+            msg_tx: self.msg_tx.clone(),
+            query_tx: self.query_tx.clone(),
+        }
+    }
+}
+
+impl<M> ProgramHandle<M> {
+    pub(crate) fn new(
+        msg_tx: mpsc::Sender<Message>,
+        query_tx: mpsc::UnboundedSender<QueryFn<M>>,
+    ) -> Self {
+        Self { msg_tx, query_tx }
+    }
+
+    /// Sends a message, waiting for space in the message queue.
+    pub async fn send(&self, msg: Message) -> Result<(), SendError<Message>> {
+        self.msg_tx.send(msg).await
+    }
+
+    /// Sends a message without waiting. The message is returned if the queue is full or the
+    /// program has stopped.
+    pub fn try_send(&self, msg: Message) -> Result<(), TrySendError<Message>> {
+        self.msg_tx.try_send(msg)
+    }
+
+    /// Asks the program to quit, waiting for space in the message queue.
+    pub async fn quit(&self, request: QuitRequest) -> Result<(), SendError<Message>> {
+        self.send(Message::Quit(request)).await
+    }
+
+    /// Asks the program to quit without waiting.
+    pub fn try_quit(&self, request: QuitRequest) -> Result<(), TrySendError<Message>> {
+        self.try_send(Message::Quit(request))
+    }
+
+    /// Runs `f` against the model on the program's thread and returns the result.
+    ///
+    /// Queries are answered while the program waits for messages, so they see the model as
+    /// of the last processed message.
+    pub async fn query<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&M) -> R + Send + 'static,
+    ) -> Result<R, QueryError> {
+        self.send_query(f)?.await.map_err(|_| QueryError)
+    }
+
+    /// Like [`ProgramHandle::query`], but blocks the current thread until the query is
+    /// answered.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from async code. Calling this from the thread that drives the program
+    /// blocks forever.
+    pub fn query_blocking<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&M) -> R + Send + 'static,
+    ) -> Result<R, QueryError> {
+        self.send_query(f)?.blocking_recv().map_err(|_| QueryError)
+    }
+
+    fn send_query<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&M) -> R + Send + 'static,
+    ) -> Result<oneshot::Receiver<R>, QueryError> {
+        let (tx, rx) = oneshot::channel();
+        self.query_tx
+            .send(Box::new(move |model| {
+                // The caller may have stopped waiting for the answer
+                let _ = tx.send(f(model));
+            }))
+            .map_err(|_| QueryError)?;
+        Ok(rx)
+    }
+}
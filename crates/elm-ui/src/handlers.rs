@@ -0,0 +1,67 @@
+use std::{
+    any::{Any, TypeId},
+    collections::{HashMap, HashSet},
+};
+
+use crate::{AsyncModel, CustomMessage, Message, OptionalCommand};
+
+type Handler<M> = Box<
+    dyn FnMut(&mut M, CustomMessage) -> Result<OptionalCommand, <M as AsyncModel>::Error> + Send,
+>;
+
+/// Handlers for custom messages, keyed by the type of the message.
+pub(crate) struct TypedHandlers<M: AsyncModel> {
+    handlers: HashMap<TypeId, Handler<M>>,
+    unhandled: HashSet<TypeId>,
+}
+
+impl<M: AsyncModel> Default for TypedHandlers<M> {
+    fn default() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            unhandled: HashSet::new(),
+        }
+    }
+}
+
+impl<M: AsyncModel> TypedHandlers<M> {
+    pub(crate) fn insert<T: Any + Send>(
+        &mut self,
+        mut handler: impl FnMut(&mut M, T) -> Result<OptionalCommand, M::Error> + Send + 'static,
+    ) {
+        self.handlers.insert(
+            TypeId::of::<T>(),
+            Box::new(move |model, msg| {
+                let Ok(msg) = msg.downcast::<T>() else {
+                    unreachable!("handlers are keyed by the type of the message");
+                };
+                handler(model, msg)
+            }),
+        );
+    }
+
+    /// Passes `msg` to the handler registered for its type. Returns the message if there's no
+    /// such handler.
+    pub(crate) fn dispatch(
+        &mut self,
+        model: &mut M,
+        msg: Message,
+    ) -> Result<Result<OptionalCommand, M::Error>, Message> {
+        if self.handlers.is_empty() {
+            return Err(msg);
+        }
+        let Message::Custom(custom) = msg else {
+            return Err(msg);
+        };
+        match self.handlers.get_mut(&custom.type_id()) {
+            Some(handler) => Ok(handler(model, custom)),
+            None => Err(Message::Custom(custom)),
+        }
+    }
+
+    /// Returns `true` the first time a message of a type without a handler is passed here, as
+    /// long as any handlers are registered.
+    pub(crate) fn is_new_unhandled(&mut self, msg: &CustomMessage) -> bool {
+        !self.handlers.is_empty() && self.unhandled.insert(msg.type_id())
+    }
+}
@@ -1,48 +1,128 @@
+mod async_model;
+mod builder;
+mod custom;
+mod error;
+mod exit;
 pub mod future_ext;
+mod handle;
+mod handlers;
+mod limits;
+mod middleware;
+mod progress;
+mod queue;
+mod registry;
+mod shutdown;
+mod typed;
+
+pub use async_model::AsyncModel;
+pub use builder::{ErrorPolicy, PanicPolicy, ProgramBuilder};
+pub use custom::CustomMessage;
+pub use error::{Channel, CommandInfo, CommandKind, MessageError, ProgramError};
+pub use exit::{ProgramExit, QuitDecision, QuitRequest};
+pub use handle::{ProgramHandle, QueryError};
+pub use limits::{ConcurrencyLimits, QueueOrder};
+pub use middleware::{Middleware, SharedUpdate};
+pub use progress::{Progress, ProgressReporter};
+pub use queue::{Envelope, FrameBudget, Priority};
+pub use shutdown::{ShutdownReport, ShutdownTask, TaskFailure};
+pub use typed::{MessageSet, TypedMessage};
+
+#[cfg(feature = "derive")]
+pub use elm_ui_derive::{MessageSet, TypedMessage};
 
 use async_recursion::async_recursion;
-use futures::{stream::FuturesUnordered, Stream, StreamExt};
+use futures::{
+    FutureExt, Stream, StreamExt,
+    future::{self, BoxFuture, OptionFuture, select_all, try_join_all},
+    stream::{self, BoxStream, FuturesUnordered},
+};
 use std::{
     any::Any,
-    collections::HashMap,
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
     fmt::Debug,
-    future::{self, Future},
+    future::Future,
+    panic,
     pin::Pin,
-    rc::Rc,
-    sync::{Arc, Mutex},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
 };
 use tokio::{
-    runtime::Handle,
-    sync::mpsc,
-    task::{self, JoinError, JoinHandle},
+    runtime::{self, Handle, RuntimeFlavor},
+    sync::mpsc::{
+        self,
+        error::{SendError, TrySendError},
+    },
+    task::{self, AbortHandle, JoinError, JoinHandle, JoinSet},
+};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use crate::{
+    handle::QueryFn, handlers::TypedHandlers, limits::CommandScheduler, middleware::SharedUpdateFn,
+    queue::MessageQueue, registry::CommandRegistry,
 };
-use tokio_util::sync::CancellationToken;
 
 pub type AsyncCommand = dyn FnOnce(
         mpsc::Sender<Command>,
         CancellationToken,
+        ProgressReporter,
     ) -> Pin<Box<dyn Future<Output = Option<Message>> + Send>>
     + Send;
-pub type BlockingCommand =
-    dyn FnOnce(mpsc::Sender<Command>, CancellationToken) -> Option<Message> + Send;
+pub type BlockingCommand = dyn FnOnce(mpsc::Sender<Command>, CancellationToken, ProgressReporter) -> Option<Message>
+    + Send;
+pub type MapFn = dyn FnOnce(Message) -> Message + Send;
+pub type ThenFn = dyn FnOnce(Message) -> Command + Send;
+pub type JoinFn = dyn FnOnce(Vec<Option<Message>>) -> Message + Send;
 
 pub enum CommandFn {
+    Ready(Message),
     Async(Box<AsyncCommand>),
     Blocking(Box<BlockingCommand>),
+    Map(Box<Command>, Box<MapFn>),
+    Then(Box<Command>, Box<ThenFn>),
+    Race(Vec<Command>),
+    Join(Vec<Command>, Box<JoinFn>),
 }
 
 impl Debug for CommandFn {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::Ready(arg0) => f.debug_tuple("Ready").field(arg0).finish(),
             Self::Async(_) => f.debug_tuple("Async").field(&"Fn").finish(),
             Self::Blocking(_) => f.debug_tuple("Blocking").field(&"Fn").finish(),
+            Self::Map(arg0, _) => f.debug_tuple("Map").field(arg0).field(&"Fn").finish(),
+            Self::Then(arg0, _) => f.debug_tuple("Then").field(arg0).field(&"Fn").finish(),
+            Self::Race(arg0) => f.debug_tuple("Race").field(arg0).finish(),
+            Self::Join(arg0, _) => f.debug_tuple("Join").field(arg0).field(&"Fn").finish(),
+        }
+    }
+}
+
+impl CommandFn {
+    pub fn kind(&self) -> CommandKind {
+        match self {
+            Self::Ready(_) => CommandKind::Ready,
+            Self::Async(_) => CommandKind::Async,
+            Self::Blocking(_) => CommandKind::Blocking,
+            Self::Map(_, _) => CommandKind::Map,
+            Self::Then(_, _) => CommandKind::Then,
+            Self::Race(_) => CommandKind::Race,
+            Self::Join(_, _) => CommandKind::Join,
         }
     }
 }
 
+static NEXT_COMMAND_ID: AtomicU64 = AtomicU64::new(0);
+
 #[derive(Debug)]
 pub struct Command {
+    id: u64,
     name: String,
+    priority: i32,
     func: CommandFn,
 }
 
@@ -50,50 +130,191 @@ impl Command {
     pub fn new_async<F: Future<Output = Option<Message>> + Send + 'static>(
         f: impl FnOnce(mpsc::Sender<Command>, CancellationToken) -> F + Send + 'static,
     ) -> Self {
-        Self {
-            name: "".to_owned(),
-            func: CommandFn::Async(Box::new(|sender, cancellation_token| {
-                Box::pin(async move { f(sender, cancellation_token).await })
-            })),
-        }
+        Self::new_async_with_progress(|sender, cancellation_token, _| f(sender, cancellation_token))
+    }
+
+    pub fn new_async_with_progress<F: Future<Output = Option<Message>> + Send + 'static>(
+        f: impl FnOnce(mpsc::Sender<Command>, CancellationToken, ProgressReporter) -> F + Send + 'static,
+    ) -> Self {
+        Self::from_fn(CommandFn::Async(Box::new(
+            |sender, cancellation_token, progress| {
+                Box::pin(async move { f(sender, cancellation_token, progress).await })
+            },
+        )))
     }
 
     pub fn new_blocking(
         f: impl FnOnce(mpsc::Sender<Command>, CancellationToken) -> Option<Message> + Send + 'static,
     ) -> Self {
-        Self {
-            name: "".to_owned(),
-            func: CommandFn::Blocking(Box::new(f)),
-        }
+        Self::new_blocking_with_progress(|sender, cancellation_token, _| {
+            f(sender, cancellation_token)
+        })
+    }
+
+    pub fn new_blocking_with_progress(
+        f: impl FnOnce(mpsc::Sender<Command>, CancellationToken, ProgressReporter) -> Option<Message>
+        + Send
+        + 'static,
+    ) -> Self {
+        Self::from_fn(CommandFn::Blocking(Box::new(f)))
     }
 
+    /// Creates a command that returns a message that's already been computed.
+    ///
+    /// Unlike other commands, these don't need to spawn a task. Messages that don't need any
+    /// further processing by the runtime are delivered directly to the message queue once the
+    /// current frame has been rendered, so a model that keeps answering with ready messages
+    /// can't starve rendering.
     pub fn simple(msg: Message) -> Self {
-        Self::new_async(|_, _| future::ready(Some(msg)))
+        Self::from_fn(CommandFn::Ready(msg))
     }
 
     pub fn quit() -> Self {
-        Self::simple(Message::Quit)
+        Self::simple(Message::Quit(QuitRequest::new()))
+    }
+
+    /// Quits with an exit code that's returned from [`Program::run`].
+    pub fn quit_with_code(code: i32) -> Self {
+        Self::simple(Message::Quit(QuitRequest::new().with_code(code)))
+    }
+
+    /// Quits with a value that's returned from [`Program::run`].
+    pub fn quit_with(value: impl Any + Send) -> Self {
+        Self::simple(Message::Quit(QuitRequest::new().with_value(value)))
+    }
+
+    /// Quits without consulting [`Model::on_quit`].
+    pub fn force_quit() -> Self {
+        Self::simple(Message::Quit(QuitRequest::new().forced()))
+    }
+
+    /// Runs all commands concurrently and resolves to the result of the first one to finish.
+    /// The remaining commands are cancelled.
+    pub fn race(cmds: impl IntoIterator<Item = Command>) -> Self {
+        Self::from_fn(CommandFn::Race(cmds.into_iter().collect()))
+    }
+
+    /// Runs all commands concurrently and combines their results, in the order the commands
+    /// were given, into a single message.
+    pub fn join(
+        cmds: impl IntoIterator<Item = Command>,
+        f: impl FnOnce(Vec<Option<Message>>) -> Message + Send + 'static,
+    ) -> Self {
+        Self::from_fn(CommandFn::Join(cmds.into_iter().collect(), Box::new(f)))
+    }
+
+    /// Transforms the message returned by this command.
+    pub fn map(self, f: impl FnOnce(Message) -> Message + Send + 'static) -> Self {
+        Self::from_fn(CommandFn::Map(Box::new(self), Box::new(f)))
+    }
+
+    /// Runs the command returned by `f` after this command finishes, passing it the message
+    /// returned by this command. Nothing else runs if this command doesn't return a message.
+    pub fn then(self, f: impl FnOnce(Message) -> Command + Send + 'static) -> Self {
+        Self::from_fn(CommandFn::Then(Box::new(self), Box::new(f)))
     }
 
+    /// Identifies this command in errors and shutdown reports.
+    pub fn info(&self) -> CommandInfo {
+        CommandInfo {
+            id: self.id,
+            name: self.name.clone(),
+            kind: self.func.kind(),
+        }
+    }
+
+    fn from_fn(func: CommandFn) -> Self {
+        Self {
+            id: NEXT_COMMAND_ID.fetch_add(1, Ordering::Relaxed),
+            name: "".to_owned(),
+            priority: 0,
+            func,
+        }
+    }
+
+    /// Returns the message if this command can be delivered without being run.
+    fn into_ready(self) -> Result<Message, Self> {
+        match self.func {
+            CommandFn::Ready(msg) if !msg.is_command_message() => Ok(msg),
+            func => Err(Self { func, ..self }),
+        }
+    }
+
+    /// Composite commands share their name with any nested commands that don't have a name of
+    /// their own, so cancelling the composite command cancels all of its unnamed parts.
     pub fn with_name(self, name: impl Into<String>) -> Self {
         Self {
             name: name.into(),
-            func: self.func,
+            ..self
+        }
+    }
+
+    /// Sets the priority used when the command is queued behind a concurrency limit with
+    /// [`QueueOrder::Priority`]. Higher values start first.
+    pub fn with_priority(self, priority: i32) -> Self {
+        Self { priority, ..self }
+    }
+}
+
+pub type AbortFn = dyn Fn(&Message) -> bool + Send;
+
+/// Commands that run one after another.
+///
+/// Each command's result is fully processed before the next command starts, so a step that
+/// returns a nested sequence or a stream finishes before the outer sequence continues.
+/// The sequence stops early if its cancellation token is cancelled or, when configured with
+/// [`Sequence::abort_on`], after a step returns a failure message.
+pub struct Sequence {
+    cmds: Vec<Command>,
+    abort_on: Option<Box<AbortFn>>,
+}
+
+impl Sequence {
+    pub fn new(cmds: impl IntoIterator<Item = Command>) -> Self {
+        Self {
+            cmds: cmds.into_iter().collect(),
+            abort_on: None,
+        }
+    }
+
+    /// Stops the sequence after a step returns a message matching `f`. The matching message is
+    /// still delivered.
+    pub fn abort_on(self, f: impl Fn(&Message) -> bool + Send + 'static) -> Self {
+        Self {
+            abort_on: Some(Box::new(f)),
+            ..self
         }
     }
 }
 
+impl From<Vec<Command>> for Sequence {
+    fn from(cmds: Vec<Command>) -> Self {
+        Self::new(cmds)
+    }
+}
+
+impl Debug for Sequence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sequence")
+            .field("cmds", &self.cmds)
+            .field("abort_on", &self.abort_on.as_ref().map(|_| "Fn"))
+            .finish()
+    }
+}
+
 pub enum Message {
     Batch(Vec<Command>),
-    Sequence(Vec<Command>),
+    Sequence(Sequence),
     Stream(Pin<Box<dyn Stream<Item = Message> + Send>>),
     #[cfg(feature = "crossterm")]
     TermEvent(crossterm::event::Event),
-    Quit,
+    Quit(QuitRequest),
     CancelAll,
     Cancel(String),
     CancellationComplete(Option<String>),
-    Custom(Box<dyn Any + Send>),
+    Progress(Progress),
+    Custom(CustomMessage),
+    Envelope(Box<Envelope>),
 }
 
 impl Debug for Message {
@@ -104,20 +325,92 @@ impl Debug for Message {
             Self::Stream(_) => f.debug_tuple("Stream").field(&"<stream>").finish(),
             #[cfg(feature = "crossterm")]
             Self::TermEvent(arg0) => f.debug_tuple("TermEvent").field(arg0).finish(),
-            Self::Quit => write!(f, "Quit"),
+            Self::Quit(arg0) => f.debug_tuple("Quit").field(arg0).finish(),
             Self::CancelAll => write!(f, "CancelAll"),
             Self::Cancel(arg0) => f.debug_tuple("Cancel").field(arg0).finish(),
             Self::CancellationComplete(arg0) => {
                 f.debug_tuple("CancellationComplete").field(arg0).finish()
             }
+            Self::Progress(arg0) => f.debug_tuple("Progress").field(arg0).finish(),
             Self::Custom(arg0) => f.debug_tuple("Custom").field(arg0).finish(),
+            Self::Envelope(arg0) => f.debug_tuple("Envelope").field(arg0).finish(),
         }
     }
 }
 
 impl Message {
     pub fn custom(msg: impl Any + Send) -> Self {
-        Self::Custom(Box::new(msg))
+        Self::Custom(CustomMessage::new(msg))
+    }
+
+    /// Like [`Message::custom`], but the message is formatted with the payload's [`Debug`]
+    /// implementation instead of just its type name.
+    pub fn custom_debug(msg: impl Any + Send + Debug) -> Self {
+        Self::Custom(CustomMessage::with_debug(msg))
+    }
+
+    pub fn sequence(cmds: impl IntoIterator<Item = Command>) -> Self {
+        Self::Sequence(Sequence::new(cmds))
+    }
+
+    /// Sets the priority used when this message waits in the message queue.
+    pub fn with_priority(self, priority: Priority) -> Self {
+        let mut envelope = self.into_envelope();
+        envelope.set_priority(priority);
+        Self::Envelope(Box::new(envelope))
+    }
+
+    /// Replaces any pending message in the message queue that has the same key, so only the
+    /// latest one is processed.
+    pub fn coalesce_by(self, key: impl Into<Cow<'static, str>>) -> Self {
+        let mut envelope = self.into_envelope();
+        envelope.set_coalesce_key(key.into());
+        Self::Envelope(Box::new(envelope))
+    }
+
+    fn into_envelope(self) -> Envelope {
+        match self {
+            Self::Envelope(envelope) => *envelope,
+            msg => Envelope::new(msg),
+        }
+    }
+
+    /// Messages that originate from user input.
+    pub fn is_input(&self) -> bool {
+        #[cfg(feature = "crossterm")]
+        if let Self::TermEvent(_) = self {
+            return true;
+        }
+        false
+    }
+
+    /// Terminal events where only the latest pending one matters.
+    pub(crate) fn event_coalesce_key(&self) -> Option<&'static str> {
+        #[cfg(feature = "crossterm")]
+        if let Self::TermEvent(event) = self {
+            use crossterm::event::{Event, MouseEventKind};
+
+            return match event {
+                Event::Resize(_, _) => Some("term-resize"),
+                Event::Mouse(mouse) if mouse.kind == MouseEventKind::Moved => {
+                    Some("term-mouse-move")
+                }
+                _ => None,
+            };
+        }
+        None
+    }
+
+    /// Messages that are interpreted by the runtime instead of being passed to the model.
+    fn is_command_message(&self) -> bool {
+        matches!(
+            self,
+            Self::Batch(_)
+                | Self::Sequence(_)
+                | Self::Stream(_)
+                | Self::CancelAll
+                | Self::Cancel(_)
+        )
     }
 }
 
@@ -126,94 +419,354 @@ pub type OptionalCommand = Option<Command>;
 pub trait Model {
     type Writer;
     type Error: std::error::Error + ToString;
+    /// Startup configuration passed to [`Model::init`], so the same model can be started with
+    /// different settings. Use `()` if the model doesn't need any.
+    type Flags;
 
-    fn init(&mut self) -> Result<OptionalCommand, Self::Error>;
-    fn update(&mut self, msg: Rc<Message>) -> Result<OptionalCommand, Self::Error>;
+    fn init(&mut self, flags: Self::Flags) -> Result<OptionalCommand, Self::Error>;
+    fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error>;
     fn view(&self, writer: &mut Self::Writer) -> Result<(), Self::Error>;
+
+    /// Called when the program is asked to quit, unless the request is forced.
+    fn on_quit(&mut self, _request: &QuitRequest) -> Result<QuitDecision, Self::Error> {
+        Ok(QuitDecision::Allow)
+    }
+
+    /// Called when the program shuts down, after all running commands have been cancelled.
+    /// The returned command runs to completion, subject to the shutdown timeout, but any
+    /// messages it produces are discarded.
+    fn on_shutdown(&mut self) -> Result<OptionalCommand, Self::Error> {
+        Ok(None)
+    }
 }
 
-pub struct Program<M: Model> {
+pub struct Program<M: AsyncModel> {
     model: M,
+    flags: Option<M::Flags>,
     cmd_tx: mpsc::Sender<Command>,
     cmd_rx: Option<mpsc::Receiver<Command>>,
     msg_tx: mpsc::Sender<Message>,
-    msg_rx: mpsc::Receiver<Message>,
+    msg_queue: MessageQueue,
+    frame_budget: FrameBudget,
+    render_interval: Option<Duration>,
     #[cfg(feature = "crossterm")]
     spawn_event_handler: bool,
+    concurrency_limits: ConcurrencyLimits,
+    error_policy: ErrorPolicy,
+    panic_policy: PanicPolicy,
+    runtime: Option<Handle>,
+    event_sources: Vec<BoxStream<'static, Message>>,
+    middleware: Vec<Box<dyn Middleware<M> + Send>>,
+    shared_update: Option<SharedUpdateFn<M>>,
+    handlers: TypedHandlers<M>,
     event_handler_task: Option<task::JoinHandle<Result<(), MessageError>>>,
-    message_handler_task: Option<JoinHandle<Result<(), MessageError>>>,
+    message_handler_task: Option<JoinHandle<Result<ShutdownReport, MessageError>>>,
     handler_cancellation_token: CancellationToken,
-    cancellation_tokens: Arc<Mutex<HashMap<String, CancellationToken>>>,
+    handler_abort_token: CancellationToken,
+    shutdown_timeout: Option<Duration>,
+    registry: Arc<CommandRegistry>,
+    quit_request: Option<QuitRequest>,
+    pending_cmds: VecDeque<Command>,
+    /// Ready messages returned during the current frame.
+    ready_msgs: Vec<Message>,
+    owned_runtime: Option<OwnedRuntime>,
+    query_tx: mpsc::UnboundedSender<QueryFn<M>>,
+    query_rx: mpsc::UnboundedReceiver<QueryFn<M>>,
 }
 
-impl<M: Model> Program<M> {
-    pub fn new(model: M) -> Self {
-        let (cmd_tx, cmd_rx) = mpsc::channel::<Command>(32);
-        let (msg_tx, msg_rx) = mpsc::channel::<Message>(32);
-        Self {
-            model,
-            cmd_tx,
-            cmd_rx: Some(cmd_rx),
-            msg_tx,
-            msg_rx,
-            #[cfg(feature = "crossterm")]
-            spawn_event_handler: true,
-            event_handler_task: None,
-            message_handler_task: None,
-            handler_cancellation_token: CancellationToken::new(),
-            cancellation_tokens: Default::default(),
-        }
+impl<M: AsyncModel> Program<M> {
+    /// Creates a program with the default configuration. Use [`Program::builder`] to
+    /// configure it.
+    pub fn new(model: M, flags: M::Flags) -> Self {
+        ProgramBuilder::new(model, flags).build()
     }
 
-    #[cfg(feature = "crossterm")]
-    pub fn with_spawn_event_handler(self, spawn_event_handler: bool) -> Self {
-        Self {
-            spawn_event_handler,
-            ..self
-        }
+    pub fn builder(model: M, flags: M::Flags) -> ProgramBuilder<M> {
+        ProgramBuilder::new(model, flags)
     }
 
-    pub async fn run(mut self, writer: &mut M::Writer) -> Result<M, ProgramError<M>> {
+    /// Runs the program until it quits. Tasks that fail while shutting down don't make this
+    /// fail. They're listed in the returned [`ProgramExit::report`] along with the model and
+    /// the exit code.
+    pub async fn run(mut self, writer: &mut M::Writer) -> Result<ProgramExit<M>, ProgramError<M>> {
         self.initialize().await?;
-        self.view(writer)
-            .map_err(ProgramError::ApplicationFailure)?;
-        while let Some(msg) = self.recv_msg().await {
+        self.render(writer).await?;
+        let mut last_render = Instant::now();
+        let mut render_pending = false;
+        loop {
+            let msg = match self.render_interval {
+                Some(interval) if render_pending => {
+                    let next_render = tokio::time::Instant::from_std(last_render + interval);
+                    tokio::select! {
+                        msg = self.recv_msg() => msg?,
+                        _ = tokio::time::sleep_until(next_render) => {
+                            self.render(writer).await?;
+                            last_render = Instant::now();
+                            render_pending = false;
+                            continue;
+                        }
+                    }
+                }
+                _ => self.recv_msg().await?,
+            };
+            let Some(msg) = msg else {
+                break;
+            };
             let quit_behavior = self.update(msg).await?;
-            self.view(writer)
-                .map_err(ProgramError::ApplicationFailure)?;
+            // Always render the final state before quitting
+            if quit_behavior == QuitBehavior::Quit
+                || self
+                    .render_interval
+                    .is_none_or(|interval| last_render.elapsed() >= interval)
+            {
+                self.render(writer).await?;
+                last_render = Instant::now();
+                render_pending = false;
+            } else {
+                render_pending = true;
+            }
             if quit_behavior == QuitBehavior::Quit {
-                return Ok(self.shutdown().await);
+                break;
             }
         }
-        Ok(self.model)
+        self.shutdown().await
+    }
+
+    /// Runs the program to completion from synchronous code.
+    ///
+    /// Outside of a runtime, a new multi-threaded runtime is created to run the program. Inside
+    /// a multi-threaded runtime, the current worker thread is used to run the program. A
+    /// current-thread runtime can't be blocked, so the program is run on a separate thread with
+    /// its own runtime instead.
+    pub fn run_blocking(self, writer: &mut M::Writer) -> Result<ProgramExit<M>, ProgramError<M>>
+    where
+        M: Send,
+        M::Writer: Send,
+        M::Error: Send,
+        M::Flags: Send,
+    {
+        match Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                task::block_in_place(|| handle.block_on(self.run(writer)))
+            }
+            Ok(_) => thread::scope(|scope| {
+                scope
+                    .spawn(|| Self::run_on_new_runtime(self, writer))
+                    .join()
+                    .unwrap_or_else(|e| panic::resume_unwind(e))
+            }),
+            Err(_) => Self::run_on_new_runtime(self, writer),
+        }
+    }
+
+    fn run_on_new_runtime(
+        program: Self,
+        writer: &mut M::Writer,
+    ) -> Result<ProgramExit<M>, ProgramError<M>> {
+        let runtime = runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(ProgramError::RuntimeFailure)?;
+        runtime.block_on(program.run(writer))
+    }
+
+    /// Registers a handler for custom messages of type `T`, replacing any previous handler
+    /// for that type. Messages without a handler are passed to [`Model::update`].
+    ///
+    /// Handled messages are moved into the handler, so middleware doesn't see them after the
+    /// update, even with shared messages enabled. The first time a custom message type without
+    /// a handler is received, it's reported to [`Middleware::on_unhandled`].
+    pub fn on<T: Any + Send>(
+        &mut self,
+        handler: impl FnMut(&mut M, T) -> Result<OptionalCommand, M::Error> + Send + 'static,
+    ) -> &mut Self {
+        self.handlers.insert(handler);
+        self
+    }
+
+    /// Creates a handle for sending messages and running queries from other tasks and
+    /// threads.
+    pub fn handle(&self) -> ProgramHandle<M> {
+        ProgramHandle::new(self.msg_tx.clone(), self.query_tx.clone())
     }
 
     pub fn cmd_tx(&self) -> mpsc::Sender<Command> {
         self.cmd_tx.clone()
     }
 
-    pub async fn recv_msg(&mut self) -> Option<Message> {
-        self.msg_rx.recv().await
+    /// Sends messages directly to the message queue. Unlike [`Program::cmd_tx`], this doesn't
+    /// require wrapping the message in a command.
+    pub fn msg_tx(&self) -> mpsc::Sender<Message> {
+        self.msg_tx.clone()
+    }
+
+    /// Queues a message to be processed during the next update without going through any
+    /// channels.
+    pub fn enqueue_msg(&mut self, msg: Message) {
+        self.msg_queue.push(msg);
     }
 
-    pub fn view(&self, writer: &mut M::Writer) -> Result<(), M::Error> {
-        self.model.view(writer)
+    /// Waits for the next message, answering queries from [`ProgramHandle`]s in the meantime.
+    /// Returns an error as soon as the message handler fails, e.g.
+    /// because a command returned an error or panicked.
+    pub async fn recv_msg(&mut self) -> Result<Option<Message>, ProgramError<M>> {
+        let res = loop {
+            tokio::select! {
+                msg = self.msg_queue.recv() => return Ok(msg),
+                Some(query) = self.query_rx.recv() => query(&self.model),
+                Some(res) = OptionFuture::from(self.message_handler_task.as_mut()) => break res,
+            }
+        };
+        self.message_handler_task = None;
+        // Without an error, the handler only stops once it's been told to shut down
+        match handler_failure(res) {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
     }
 
-    pub async fn shutdown(mut self) -> M {
-        for token in self.cancellation_tokens.lock().unwrap().values() {
-            token.cancel();
+    /// Shuts down a program driven by [`Program::step`]. Like [`Program::run_blocking`], this
+    /// may be called from inside a multi-threaded runtime, but not from a current-thread
+    /// runtime since the program's tasks couldn't make progress while it blocks.
+    pub fn shutdown_blocking(self) -> Result<ProgramExit<M>, ProgramError<M>> {
+        if let Ok(handle) = Handle::try_current() {
+            return task::block_in_place(|| handle.block_on(self.shutdown()));
+        }
+        match &self.runtime {
+            Some(runtime) => runtime.clone().block_on(self.shutdown()),
+            None => runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(ProgramError::RuntimeFailure)?
+                .block_on(self.shutdown()),
+        }
+    }
+
+    async fn render(&mut self, writer: &mut M::Writer) -> Result<(), ProgramError<M>> {
+        let res = self.model.view(writer).await;
+        self.check_model_result(res)?;
+        Ok(())
+    }
+
+    /// Applies the [`ErrorPolicy`] to a result returned by the model. Returns `None` if the
+    /// error should be ignored.
+    fn check_model_result<T>(
+        &mut self,
+        res: Result<T, M::Error>,
+    ) -> Result<Option<T>, ProgramError<M>> {
+        match res {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => {
+                for middleware in &mut self.middleware {
+                    middleware.on_error(&self.model, &e);
+                }
+                match self.error_policy {
+                    ErrorPolicy::Exit => Err(ProgramError::ApplicationFailure(e)),
+                    ErrorPolicy::Continue => Ok(None),
+                }
+            }
+        }
+    }
+
+    fn spawn<T: Send + 'static>(
+        &self,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> JoinHandle<T> {
+        match &self.runtime {
+            Some(runtime) => runtime.spawn(future),
+            None => task::spawn(future),
+        }
+    }
+
+    /// Cancels all running and queued commands, runs the command returned by
+    /// [`Model::on_shutdown`], and waits for every task to finish. Tasks still running when the
+    /// shutdown timeout expires are aborted. Messages produced while shutting down are
+    /// discarded, as are commands that haven't been sent yet.
+    ///
+    /// An error from [`Model::on_shutdown`] is only returned after the program has been torn
+    /// down.
+    pub async fn shutdown(mut self) -> Result<ProgramExit<M>, ProgramError<M>> {
+        self.registry.cancel_all();
+        let mut result = Ok(());
+        match self.model.on_shutdown() {
+            Ok(Some(cmd)) if self.message_handler_task.is_some() => {
+                result = Self::send_cmd(&self.cmd_tx, cmd).await;
+            }
+            Ok(_) => {}
+            Err(e) => result = Err(ProgramError::ApplicationFailure(e)),
         }
 
         self.handler_cancellation_token.cancel();
-        if let Some(handler) = self.event_handler_task.take() {
-            handler.await.unwrap().unwrap();
+        let deadline = self
+            .shutdown_timeout
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+        let mut report = ShutdownReport::default();
+
+        if let Some(mut handler) = self.event_handler_task.take() {
+            match self.drain_until(&mut handler, deadline).await {
+                Some(Ok(Ok(()))) => {}
+                Some(Ok(Err(error))) => report.failed.push(TaskFailure {
+                    task: ShutdownTask::EventHandler,
+                    error,
+                }),
+                Some(Err(e)) => report.failed.push(TaskFailure {
+                    task: ShutdownTask::EventHandler,
+                    error: MessageError::JoinFailure(e),
+                }),
+                None => {
+                    handler.abort();
+                    report.aborted.push(ShutdownTask::EventHandler);
+                }
+            }
         }
 
-        if let Some(handler) = self.message_handler_task.take() {
-            handler.await.unwrap().unwrap();
+        if let Some(mut handler) = self.message_handler_task.take() {
+            let res = match self.drain_until(&mut handler, deadline).await {
+                Some(res) => res,
+                None => {
+                    // The handler aborts its remaining tasks and reports them
+                    self.handler_abort_token.cancel();
+                    self.drain_until(&mut handler, None)
+                        .await
+                        .expect("no deadline was set")
+                }
+            };
+            match res {
+                Ok(Ok(handler_report)) => report.merge(handler_report),
+                Ok(Err(error)) => report.failed.push(TaskFailure {
+                    task: ShutdownTask::MessageHandler,
+                    error,
+                }),
+                Err(e) => report.failed.push(TaskFailure {
+                    task: ShutdownTask::MessageHandler,
+                    error: MessageError::JoinFailure(e),
+                }),
+            }
+        }
+        result.map(|()| ProgramExit::new(self.model, self.quit_request, report))
+    }
+
+    /// Waits for `handler` to finish, discarding messages in the meantime so it can't get stuck
+    /// on a full message queue. Returns `None` if the deadline passes first.
+    async fn drain_until<T>(
+        &mut self,
+        handler: &mut JoinHandle<T>,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Option<Result<T, JoinError>> {
+        let timeout = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => future::pending().await,
+            }
+        };
+        tokio::pin!(timeout);
+        loop {
+            tokio::select! {
+                res = &mut *handler => return Some(res),
+                _ = &mut timeout => return None,
+                _ = self.msg_queue.recv() => {}
+            }
         }
-        self.model
     }
 
     pub fn into_model(self) -> M {
@@ -221,109 +774,175 @@ impl<M: Model> Program<M> {
     }
 
     pub async fn initialize(&mut self) -> Result<(), ProgramError<M>> {
-        #[cfg(feature = "crossterm")]
-        {
-            if self.spawn_event_handler {
-                self.event_handler_task =
-                    Some(self.spawn_event_reader(self.handler_cancellation_token.clone()));
-            }
-        }
+        self.start().await?;
+        self.flush_cmds().await
+    }
+
+    async fn start(&mut self) -> Result<(), ProgramError<M>> {
+        self.event_handler_task = self.spawn_event_handler(self.handler_cancellation_token.clone());
         self.message_handler_task =
             self.spawn_message_handler(self.handler_cancellation_token.clone());
 
-        if let Some(cmd) = self
-            .model
-            .init()
-            .map_err(ProgramError::ApplicationFailure)?
-        {
-            self.cmd_tx.send(cmd).await.map_err(|e| {
-                ProgramError::MessageFailure(MessageError::SendFailure(e.to_string()))
-            })?;
+        let flags = self.flags.take().expect("the program is only started once");
+        let res = self.model.init(flags).await;
+        if let Some(Some(cmd)) = self.check_model_result(res)? {
+            self.dispatch_cmd(cmd);
         }
+        self.release_ready_msgs();
         Ok(())
     }
 
+    /// Processes `msg` along with any other pending messages, up to the configured
+    /// [`FrameBudget`].
     pub async fn update(&mut self, msg: Message) -> Result<QuitBehavior, ProgramError<M>> {
-        if QuitBehavior::Quit == self.handle_update(msg).await? {
-            return Ok(QuitBehavior::Quit);
-        }
-        while let Ok(msg) = self.msg_rx.try_recv() {
-            if QuitBehavior::Quit == self.handle_update(msg).await? {
-                return Ok(QuitBehavior::Quit);
-            }
+        let frame_start = Instant::now();
+        let mut quit_behavior = self.handle_update(msg).await?;
+        let mut processed = 1;
+        while quit_behavior == QuitBehavior::Continue
+            && !self.frame_budget.is_exhausted(processed, frame_start)
+            && let Some(msg) = self.msg_queue.try_recv()
+        {
+            quit_behavior = self.handle_update(msg).await?;
+            processed += 1;
         }
-        Ok(QuitBehavior::Continue)
+        self.release_ready_msgs();
+        Ok(quit_behavior)
     }
 
-    #[cfg(feature = "crossterm")]
-    fn spawn_event_reader(
-        &self,
+    /// Forwards messages from the terminal and any other event sources to the message queue.
+    fn spawn_event_handler(
+        &mut self,
         cancellation_token: CancellationToken,
-    ) -> task::JoinHandle<Result<(), MessageError>> {
+    ) -> Option<JoinHandle<Result<(), MessageError>>> {
         use future_ext::FutureExt;
 
+        #[allow(unused_mut)]
+        let mut sources = std::mem::take(&mut self.event_sources);
+        #[cfg(feature = "crossterm")]
+        if self.spawn_event_handler {
+            sources.push(
+                crossterm::event::EventStream::new()
+                    .filter_map(|event| future::ready(event.ok().map(Message::TermEvent)))
+                    .boxed(),
+            );
+        }
+        if sources.is_empty() {
+            return None;
+        }
+
         let msg_tx = self.msg_tx.clone();
-        task::spawn(async move {
-            let mut event_reader = crossterm::event::EventStream::new().fuse();
-            while let Ok(Some(event)) = event_reader
-                .next()
-                .cancel_on_shutdown(&cancellation_token)
-                .await
-            {
-                if let Ok(event) = event {
-                    msg_tx
-                        .send(Message::TermEvent(event))
-                        .await
-                        .map_err(|e| MessageError::SendFailure(e.to_string()))?;
-                }
+        Some(self.spawn(async move {
+            let mut events = stream::select_all(sources);
+            while let Ok(Some(msg)) = events.next().cancel_on_shutdown(&cancellation_token).await {
+                msg_tx
+                    .send(msg)
+                    .await
+                    .map_err(MessageError::send_failure(Channel::Message))?;
             }
             Ok(())
-        })
+        }))
     }
 
     fn spawn_message_handler(
         &mut self,
         cancellation_token: CancellationToken,
-    ) -> Option<JoinHandle<Result<(), MessageError>>> {
+    ) -> Option<JoinHandle<Result<ShutdownReport, MessageError>>> {
         if let Some(mut cmd_rx) = self.cmd_rx.take() {
             let msg_tx = self.msg_tx.clone();
             let cmd_tx = self.cmd_tx.clone();
-            let cancellation_tokens = self.cancellation_tokens.clone();
+            let registry = self.registry.clone();
+            let mut scheduler =
+                CommandScheduler::new(self.concurrency_limits.clone(), self.registry.clone());
+            let abort_token = self.handler_abort_token.clone();
+            let panic_policy = self.panic_policy;
 
-            Some(tokio::task::spawn(async move {
+            Some(self.spawn(async move {
                 let mut futs = FuturesUnorderedCounter::default();
-                loop {
-                    tokio::select! {
-                        Some(cmd) = cmd_rx.recv() => {
-                            {
-                                let mut cancellation_tokens = cancellation_tokens.lock().unwrap();
-                                    if !cancellation_tokens.contains_key(&cmd.name) {
-                                        cancellation_tokens.insert(cmd.name.clone(), CancellationToken::new());
+                // Stop any remaining tasks if the handler fails so they don't outlive it
+                let res = async {
+                    let mut report = ShutdownReport::default();
+                    let mut shutting_down = false;
+                    loop {
+                        tokio::select! {
+                            Some(cmd) = cmd_rx.recv() => match cmd.into_ready() {
+                                Ok(msg) => forward_msg(msg, &msg_tx, &mut futs)?,
+                                Err(cmd) => {
+                                    if let Some(cmd) = scheduler.submit(cmd) {
+                                        handle_cmd::<M>(
+                                            cmd,
+                                            msg_tx.clone(),
+                                            cmd_tx.clone(),
+                                            &mut futs,
+                                            registry.clone(),
+                                        )?;
                                     }
+                                }
+                            },
+                            Some((command, res)) = futs.next() => {
+                                if let Some(command) = &command {
+                                    scheduler.finish(&command.name);
+                                }
+                                // Failures end the handler while the program is running, unless
+                                // they're panics that should be ignored, but shouldn't prevent
+                                // other tasks from finishing during shutdown
+                                if let Err(error) =
+                                    res.map_err(MessageError::JoinFailure).and_then(|res| res)
+                                {
+                                    if shutting_down {
+                                        report.failed.push(TaskFailure {
+                                            task: ShutdownTask::from_command(command),
+                                            error,
+                                        });
+                                    } else if !(panic_policy == PanicPolicy::Continue
+                                        && error.is_panic())
+                                    {
+                                        return Err(match command {
+                                            Some(command) => MessageError::CommandFailure {
+                                                command,
+                                                source: Box::new(error),
+                                            },
+                                            None => error,
+                                        });
+                                    }
+                                }
+                                while let Some(cmd) = scheduler.next_ready() {
+                                    handle_cmd::<M>(
+                                        cmd,
+                                        msg_tx.clone(),
+                                        cmd_tx.clone(),
+                                        &mut futs,
+                                        registry.clone(),
+                                    )?;
+                                }
+                            },
+                            _ = cancellation_token.cancelled(), if !shutting_down => {
+                                shutting_down = true;
                             }
-                            handle_cmd::<M>(
-                                cmd,
-                                msg_tx.clone(),
-                                cmd_tx.clone(),
-                                &mut futs,
-                                cancellation_tokens.clone(),
-                            )?;
-                        },
-                        Some(fut) = futs.next() => {
-                            fut.map_err(MessageError::JoinFailure)??;
-                            if cancellation_token.is_cancelled() && futs.is_empty() {
-                                break;
-                            }
-                        },
-                        _ = cancellation_token.cancelled() => {
-                            if futs.is_empty() {
+                            _ = abort_token.cancelled(), if shutting_down => {
+                                report.aborted.extend(
+                                    futs.abort_all().into_iter().map(ShutdownTask::from_command),
+                                );
+                                report.aborted.extend(
+                                    scheduler
+                                        .drain_queue()
+                                        .map(|cmd| ShutdownTask::Command(cmd.info())),
+                                );
                                 break;
                             }
                         }
-
+                        // Commands sent before shutting down (e.g. from Model::on_shutdown) still
+                        // need to run
+                        if shutting_down && futs.is_empty() && cmd_rx.is_empty() {
+                            break;
+                        }
                     }
+                    Ok(report)
                 }
-                Ok(())
+                .await;
+                if res.is_err() {
+                    futs.abort_all();
+                }
+                res
             }))
         } else {
             None
@@ -331,45 +950,266 @@ impl<M: Model> Program<M> {
     }
 
     async fn handle_update(&mut self, msg: Message) -> Result<QuitBehavior, ProgramError<M>> {
-        if let Message::Quit = msg {
+        let quit_behavior = self.process_msg(msg).await?;
+        self.flush_cmds().await?;
+        Ok(quit_behavior)
+    }
+
+    /// Passes `msg` to the model. Commands are buffered until they're flushed.
+    async fn process_msg(&mut self, msg: Message) -> Result<QuitBehavior, ProgramError<M>> {
+        if let Message::Quit(request) = msg {
+            // Keep running if the model can't decide
+            if !request.is_forced()
+                && let QuitDecision::Veto(cmd) = {
+                    let res = self.model.on_quit(&request);
+                    self.check_model_result(res)?
+                        .unwrap_or(QuitDecision::Veto(None))
+                }
+            {
+                if let Some(cmd) = cmd {
+                    self.dispatch_cmd(cmd);
+                }
+                return Ok(QuitBehavior::Continue);
+            }
+            self.quit_request = Some(request);
             return Ok(QuitBehavior::Quit);
         }
-        if let Some(cmd) = self
-            .model
-            .update(Rc::new(msg))
-            .map_err(ProgramError::ApplicationFailure)?
-        {
-            self.cmd_tx.send(cmd).await.map_err(|e| {
-                ProgramError::MessageFailure(MessageError::SendFailure(e.to_string()))
-            })?;
+        if msg.is_command_message() {
+            // Sent through msg_tx or enqueue_msg, these still need to be interpreted
+            self.pending_cmds.push_back(Command::simple(msg));
+            return Ok(QuitBehavior::Continue);
+        }
+        for middleware in &mut self.middleware {
+            middleware.before_update(&self.model, &msg);
+        }
+        let (res, msg) = match self.handlers.dispatch(&mut self.model, msg) {
+            Ok(res) => (res, None),
+            Err(msg) => {
+                if let Message::Custom(custom) = &msg
+                    && self.handlers.is_new_unhandled(custom)
+                {
+                    for middleware in &mut self.middleware {
+                        middleware.on_unhandled(&self.model, custom);
+                    }
+                }
+                match self.shared_update {
+                    Some(update_shared) => (update_shared(&mut self.model, &msg), Some(msg)),
+                    None => (self.model.update(msg).await, None),
+                }
+            }
+        };
+        if let Some(cmd) = self.check_model_result(res)? {
+            for middleware in &mut self.middleware {
+                middleware.after_update(&self.model, msg.as_ref(), cmd.as_ref());
+            }
+            if let Some(cmd) = cmd {
+                self.dispatch_cmd(cmd);
+            }
         }
         Ok(QuitBehavior::Continue)
     }
+
+    fn dispatch_cmd(&mut self, cmd: Command) {
+        match cmd.into_ready() {
+            Ok(msg) => self.ready_msgs.push(msg),
+            Err(cmd) => self.pending_cmds.push_back(cmd),
+        }
+    }
+
+    /// Queues the ready messages returned during the frame that just ended.
+    fn release_ready_msgs(&mut self) {
+        for msg in self.ready_msgs.drain(..) {
+            self.msg_queue.push(msg);
+        }
+    }
+
+    async fn flush_cmds(&mut self) -> Result<(), ProgramError<M>> {
+        while let Some(cmd) = self.pending_cmds.pop_front() {
+            Self::send_cmd(&self.cmd_tx, cmd).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends as many buffered commands as the command channel has room for.
+    fn try_flush_cmds(&mut self) -> Result<(), ProgramError<M>> {
+        while let Some(cmd) = self.pending_cmds.pop_front() {
+            match self.cmd_tx.try_send(cmd) {
+                Ok(()) => {}
+                Err(TrySendError::Full(cmd)) => {
+                    self.pending_cmds.push_front(cmd);
+                    break;
+                }
+                Err(TrySendError::Closed(cmd)) => {
+                    let error = MessageError::send_failure(Channel::Command)(SendError(cmd));
+                    return Err(ProgramError::MessageFailure(error));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the sender rather than `&self`, since `Program` isn't `Sync` and holding a
+    /// reference to it across an await would make the future `!Send`.
+    async fn send_cmd(cmd_tx: &mpsc::Sender<Command>, cmd: Command) -> Result<(), ProgramError<M>> {
+        cmd_tx
+            .send(cmd)
+            .await
+            .map_err(MessageError::send_failure(Channel::Command))
+            .map_err(ProgramError::MessageFailure)
+    }
+}
+
+type CommandResult = Result<Result<(), MessageError>, JoinError>;
+
+impl<M: Model> Program<M> {
+    /// Processes the messages that are currently pending, up to the configured
+    /// [`FrameBudget`], and renders the view if anything was processed. Unlike
+    /// [`Program::run`], this never waits, so the program can be driven from the event loop of
+    /// another UI toolkit, e.g. from a repeating timer. The program is initialized by the first
+    /// call.
+    ///
+    /// Commands are sent without waiting as well. If the command channel is full, they're kept
+    /// until a later call. The render rate isn't applied since the caller decides how often
+    /// this runs. Queries from [`ProgramHandle`]s are answered at the start of each call.
+    ///
+    /// Tasks are spawned on the runtime set with [`ProgramBuilder::with_runtime`] or the
+    /// current runtime. Outside of a runtime, the program creates its own runtime. Once this
+    /// returns [`QuitBehavior::Quit`] or an error, call [`Program::shutdown_blocking`] to stop
+    /// the program's tasks.
+    pub fn step(
+        &mut self,
+        writer: &mut <M as Model>::Writer,
+    ) -> Result<QuitBehavior, ProgramError<M>> {
+        let mut needs_render = false;
+        if self.cmd_rx.is_some() {
+            if self.runtime.is_none() && Handle::try_current().is_err() {
+                let runtime = OwnedRuntime::new().map_err(ProgramError::RuntimeFailure)?;
+                self.runtime = Some(runtime.handle());
+                self.owned_runtime = Some(runtime);
+            }
+            ready(self.start())?;
+            needs_render = true;
+        }
+        if let Some(handler) = &mut self.message_handler_task
+            && handler.is_finished()
+            && let Some(res) = handler.now_or_never()
+        {
+            self.message_handler_task = None;
+            if let Some(e) = handler_failure(res) {
+                return Err(e);
+            }
+        }
+        self.try_flush_cmds()?;
+        while let Ok(query) = self.query_rx.try_recv() {
+            query(&self.model);
+        }
+
+        let frame_start = Instant::now();
+        let mut processed = 0;
+        let mut quit_behavior = QuitBehavior::Continue;
+        while quit_behavior == QuitBehavior::Continue
+            && (processed == 0 || !self.frame_budget.is_exhausted(processed, frame_start))
+            && let Some(msg) = self.msg_queue.try_recv()
+        {
+            quit_behavior = ready(self.process_msg(msg))?;
+            self.try_flush_cmds()?;
+            processed += 1;
+            needs_render = true;
+        }
+        self.release_ready_msgs();
+        if needs_render {
+            ready(self.render(writer))?;
+        }
+        Ok(quit_behavior)
+    }
+
+    pub fn view(&self, writer: &mut <M as Model>::Writer) -> Result<(), <M as Model>::Error> {
+        Model::view(&self.model, writer)
+    }
+}
+
+/// Resolves a future returned by a synchronous [`Model`], which never waits.
+fn ready<T>(future: impl Future<Output = T>) -> T {
+    future
+        .now_or_never()
+        .expect("synchronous models complete immediately")
+}
+
+fn handler_failure<M: AsyncModel>(
+    res: Result<Result<ShutdownReport, MessageError>, JoinError>,
+) -> Option<ProgramError<M>> {
+    match res {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(ProgramError::MessageFailure(e)),
+        Err(e) => Some(ProgramError::MessageFailure(MessageError::JoinFailure(e))),
+    }
+}
+
+/// A runtime created by [`Program::step`]. Shut down in the background when dropped so
+/// dropping the program from async code doesn't panic.
+struct OwnedRuntime(Option<runtime::Runtime>);
+
+impl OwnedRuntime {
+    fn new() -> std::io::Result<Self> {
+        runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map(|runtime| Self(Some(runtime)))
+    }
+
+    fn handle(&self) -> Handle {
+        self.0
+            .as_ref()
+            .expect("runtime is only taken on drop")
+            .handle()
+            .clone()
+    }
+}
+
+impl Drop for OwnedRuntime {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
 }
 
 #[derive(Default)]
 struct FuturesUnorderedCounter {
-    futures: FuturesUnordered<JoinHandle<Result<(), MessageError>>>,
-    count: usize,
+    futures: FuturesUnordered<BoxFuture<'static, (u64, CommandResult)>>,
+    tasks: BTreeMap<u64, (Option<CommandInfo>, AbortHandle)>,
+    next_id: u64,
 }
 
 impl FuturesUnorderedCounter {
-    fn push(&mut self, future: JoinHandle<Result<(), MessageError>>) {
-        self.futures.push(future);
-        self.count += 1;
+    /// `command` is set for tasks that count towards the concurrency limits.
+    fn push(&mut self, command: Option<CommandInfo>, future: JoinHandle<Result<(), MessageError>>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.tasks.insert(id, (command, future.abort_handle()));
+        self.futures.push(future.map(move |res| (id, res)).boxed());
     }
 
-    async fn next(&mut self) -> Option<Result<Result<(), MessageError>, JoinError>> {
-        let next = self.futures.next().await;
-        if next.is_some() {
-            self.count -= 1;
-        }
-
-        next
+    async fn next(&mut self) -> Option<(Option<CommandInfo>, CommandResult)> {
+        let (id, res) = self.futures.next().await?;
+        let (command, _) = self.tasks.remove(&id)?;
+        Some((command, res))
     }
 
     fn is_empty(&self) -> bool {
-        self.count == 0
+        self.tasks.is_empty()
+    }
+
+    /// Aborts every remaining task, returning the commands they were running.
+    fn abort_all(&mut self) -> Vec<Option<CommandInfo>> {
+        self.futures.clear();
+        std::mem::take(&mut self.tasks)
+            .into_values()
+            .map(|(command, handle)| {
+                handle.abort();
+                command
+            })
+            .collect()
     }
 }
 
@@ -379,168 +1219,285 @@ pub enum QuitBehavior {
     Continue,
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum MessageError {
-    #[error("{0}")]
-    SendFailure(String),
-    #[error("{0}")]
-    JoinFailure(JoinError),
+fn handle_cmd<M: AsyncModel>(
+    cmd: Command,
+    msg_tx: mpsc::Sender<Message>,
+    cmd_tx: mpsc::Sender<Command>,
+    futs: &mut FuturesUnorderedCounter,
+    registry: Arc<CommandRegistry>,
+) -> Result<(), MessageError> {
+    let info = cmd.info();
+    let name = info.name.clone();
+    futs.push(
+        Some(info),
+        tokio::task::spawn(async move {
+            let msg = execute_cmd(
+                cmd,
+                msg_tx.clone(),
+                cmd_tx.clone(),
+                registry.clone(),
+                String::new(),
+                false,
+            )
+            .await?;
+            handle_msg::<M>(msg, msg_tx, cmd_tx, registry, name).await
+        }),
+    );
+    Ok(())
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum ProgramError<M: Model> {
-    #[error("{0}")]
-    MessageFailure(MessageError),
-    #[error("{0}")]
-    ApplicationFailure(M::Error),
+fn forward_msg(
+    msg: Message,
+    msg_tx: &mpsc::Sender<Message>,
+    futs: &mut FuturesUnorderedCounter,
+) -> Result<(), MessageError> {
+    match msg_tx.try_send(msg) {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(msg)) => {
+            // Fall back to waiting in a separate task so the handler can keep receiving
+            // commands while the queue is full
+            let msg_tx = msg_tx.clone();
+            futs.push(
+                None,
+                tokio::task::spawn(async move {
+                    msg_tx
+                        .send(msg)
+                        .await
+                        .map_err(MessageError::send_failure(Channel::Message))
+                }),
+            );
+            Ok(())
+        }
+        Err(TrySendError::Closed(msg)) => {
+            Err(MessageError::send_failure(Channel::Message)(SendError(msg)))
+        }
+    }
 }
 
-fn handle_cmd<M: Model>(
+#[async_recursion]
+async fn execute_cmd(
     cmd: Command,
     msg_tx: mpsc::Sender<Message>,
     cmd_tx: mpsc::Sender<Command>,
-    futs: &mut FuturesUnorderedCounter,
-    cancellation_tokens: Arc<Mutex<HashMap<String, CancellationToken>>>,
-) -> Result<(), MessageError> {
-    let cancellation_token = cancellation_tokens
-        .lock()
-        .unwrap()
-        .get(&cmd.name)
-        .unwrap()
-        .clone();
+    registry: Arc<CommandRegistry>,
+    parent_name: String,
+    isolated: bool,
+) -> Result<Option<Message>, MessageError> {
+    let name = if cmd.name.is_empty() {
+        parent_name
+    } else {
+        cmd.name
+    };
     match cmd.func {
-        CommandFn::Async(cmd) => {
-            futs.push(tokio::task::spawn(async move {
-                let msg = cmd(cmd_tx.clone(), cancellation_token).await;
-                handle_msg::<M>(msg, msg_tx, cmd_tx, cancellation_tokens).await
-            }));
-        }
-        CommandFn::Blocking(cmd) => {
-            futs.push(tokio::task::spawn_blocking(move || {
-                let msg = cmd(cmd_tx.clone(), cancellation_token);
-                let handle: JoinHandle<Result<(), MessageError>> = tokio::task::spawn(async move {
-                    handle_msg::<M>(msg, msg_tx, cmd_tx, cancellation_tokens).await?;
-                    Ok(())
-                });
-                Handle::current()
-                    .block_on(handle)
-                    .map_err(MessageError::JoinFailure)??;
-                Ok(())
-            }));
+        CommandFn::Ready(msg) => Ok(Some(msg)),
+        CommandFn::Async(func) => {
+            let (cancellation_token, guard) = leaf_token(&registry, &name, isolated);
+            let progress = ProgressReporter::new(name, msg_tx, Handle::current());
+            let msg = func(cmd_tx, cancellation_token, progress).await;
+            guard.map(DropGuard::disarm);
+            Ok(msg)
+        }
+        CommandFn::Blocking(func) => {
+            let (cancellation_token, guard) = leaf_token(&registry, &name, isolated);
+            let progress = ProgressReporter::new(name, msg_tx, Handle::current());
+            let msg =
+                tokio::task::spawn_blocking(move || func(cmd_tx, cancellation_token, progress))
+                    .await
+                    .map_err(MessageError::JoinFailure)?;
+            guard.map(DropGuard::disarm);
+            Ok(msg)
+        }
+        CommandFn::Map(cmd, f) => {
+            let msg = execute_cmd(*cmd, msg_tx, cmd_tx, registry, name, isolated).await?;
+            Ok(msg.map(f))
+        }
+        CommandFn::Then(cmd, f) => {
+            let msg = execute_cmd(
+                *cmd,
+                msg_tx.clone(),
+                cmd_tx.clone(),
+                registry.clone(),
+                name.clone(),
+                isolated,
+            )
+            .await?;
+            match msg {
+                Some(msg) => execute_cmd(f(msg), msg_tx, cmd_tx, registry, name, isolated).await,
+                None => Ok(None),
+            }
+        }
+        CommandFn::Race(cmds) => {
+            if cmds.is_empty() {
+                return Ok(None);
+            }
+            let futs = cmds.into_iter().map(|cmd| {
+                execute_cmd(
+                    cmd,
+                    msg_tx.clone(),
+                    cmd_tx.clone(),
+                    registry.clone(),
+                    name.clone(),
+                    true,
+                )
+            });
+            // The remaining futures are dropped here, which cancels them
+            let (msg, _, _) = select_all(futs).await;
+            msg
+        }
+        CommandFn::Join(cmds, f) => {
+            let futs = cmds.into_iter().map(|cmd| {
+                execute_cmd(
+                    cmd,
+                    msg_tx.clone(),
+                    cmd_tx.clone(),
+                    registry.clone(),
+                    name.clone(),
+                    true,
+                )
+            });
+            let msgs = try_join_all(futs).await?;
+            Ok(Some(f(msgs)))
         }
     }
-    Ok(())
 }
 
+/// Commands that may be dropped before they finish (e.g. when losing a race) are isolated with a
+/// child token that gets cancelled on drop, so any work they spawned is cancelled as well without
+/// affecting other commands with the same name. Everything else uses the shared token directly.
+fn leaf_token(
+    registry: &CommandRegistry,
+    name: &str,
+    isolated: bool,
+) -> (CancellationToken, Option<DropGuard>) {
+    let token = registry.token(name);
+    if isolated {
+        let token = token.child_token();
+        (token.clone(), Some(token.drop_guard()))
+    } else {
+        (token, None)
+    }
+}
+
+/// Interprets a message returned by a command. `name` is the name of the command that returned
+/// it, which is inherited by any unnamed commands nested inside the message.
 #[async_recursion]
-async fn handle_msg<M: Model>(
+async fn handle_msg<M: AsyncModel>(
     msg: Option<Message>,
     msg_tx: mpsc::Sender<Message>,
     cmd_tx: mpsc::Sender<Command>,
-    cancellation_tokens: Arc<Mutex<HashMap<String, CancellationToken>>>,
+    registry: Arc<CommandRegistry>,
+    name: String,
 ) -> Result<(), MessageError> {
-    let mut futs = FuturesUnordered::<JoinHandle<Result<(), MessageError>>>::default();
+    // Aborts the nested tasks if this task is aborted, e.g. at the shutdown timeout
+    let mut futs = JoinSet::new();
     match msg {
         Some(Message::Batch(cmds)) => {
             for cmd in cmds {
+                let cmd = if cmd.name.is_empty() {
+                    cmd.with_name(name.clone())
+                } else {
+                    cmd
+                };
                 cmd_tx
                     .send(cmd)
                     .await
-                    .map_err(|e| MessageError::SendFailure(e.to_string()))?;
+                    .map_err(MessageError::send_failure(Channel::Command))?;
             }
         }
-        Some(Message::Sequence(cmds)) => {
+        Some(Message::Sequence(sequence)) => {
             let msg_tx = msg_tx.clone();
             let cmd_tx = cmd_tx.clone();
-            futs.push(tokio::task::spawn(async move {
-                handle_sequence_cmd(cmds, cmd_tx, msg_tx, cancellation_tokens).await
-            }));
+            futs.spawn(async move {
+                handle_sequence_cmd::<M>(sequence, cmd_tx, msg_tx, registry, name).await
+            });
         }
         Some(Message::Stream(mut rx)) => {
             let msg_tx = msg_tx.clone();
             let cmd_tx = cmd_tx.clone();
-            futs.push(task::spawn(async move {
+            futs.spawn(async move {
                 while let Some(msg) = rx.next().await {
                     let res = handle_msg::<M>(
                         Some(msg),
                         msg_tx.clone(),
                         cmd_tx.clone(),
-                        cancellation_tokens.clone(),
+                        registry.clone(),
+                        name.clone(),
                     )
                     .await;
                     res?;
                 }
                 Ok(())
-            }));
+            });
         }
         Some(Message::CancelAll) => {
-            for token in cancellation_tokens.lock().unwrap().values() {
-                token.cancel();
-            }
+            registry.cancel_all();
             msg_tx
                 .send(Message::CancellationComplete(None))
                 .await
-                .map_err(|e| MessageError::SendFailure(e.to_string()))?;
+                .map_err(MessageError::send_failure(Channel::Message))?;
         }
         Some(Message::Cancel(name)) => {
-            if let Some(token) = cancellation_tokens.lock().unwrap().get(&name) {
-                token.cancel();
-            }
+            registry.cancel(&name);
             msg_tx
                 .send(Message::CancellationComplete(Some(name)))
                 .await
-                .map_err(|e| MessageError::SendFailure(e.to_string()))?;
+                .map_err(MessageError::send_failure(Channel::Message))?;
         }
         Some(msg) => {
             msg_tx
                 .send(msg)
                 .await
-                .map_err(|e| MessageError::SendFailure(e.to_string()))?;
+                .map_err(MessageError::send_failure(Channel::Message))?;
         }
         None => {}
     }
-    while let Some(fut) = futs.next().await {
-        fut.map_err(MessageError::JoinFailure)??
+    while let Some(res) = futs.join_next().await {
+        res.map_err(MessageError::JoinFailure)??
     }
     Ok(())
 }
 
-async fn handle_sequence_cmd(
-    cmds: Vec<Command>,
+async fn handle_sequence_cmd<M: AsyncModel>(
+    sequence: Sequence,
     cmd_tx: mpsc::Sender<Command>,
     msg_tx: mpsc::Sender<Message>,
-    cancellation_tokens: Arc<Mutex<HashMap<String, CancellationToken>>>,
+    registry: Arc<CommandRegistry>,
+    name: String,
 ) -> Result<(), MessageError> {
+    use future_ext::FutureExt;
+
+    let cancellation_token = registry.token(&name).child_token();
+    let Sequence { cmds, abort_on } = sequence;
     for command in cmds {
-        let cancellation_token = cancellation_tokens
-            .lock()
-            .unwrap()
-            .get(&command.name)
-            .unwrap()
-            .clone();
-        match command.func {
-            CommandFn::Async(cmd) => {
-                if let Some(msg) = cmd(cmd_tx.clone(), cancellation_token).await {
-                    msg_tx
-                        .send(msg)
-                        .await
-                        .map_err(|e| MessageError::SendFailure(e.to_string()))?;
-                }
-            }
-            CommandFn::Blocking(cmd) => {
-                let msg_tx = msg_tx.clone();
-                let cmd_tx = cmd_tx.clone();
-                let handle: JoinHandle<Result<(), MessageError>> =
-                    tokio::task::spawn_blocking(move || {
-                        if let Some(msg) = cmd(cmd_tx, cancellation_token) {
-                            msg_tx
-                                .blocking_send(msg)
-                                .map_err(|e| MessageError::SendFailure(e.to_string()))?;
-                        }
-                        Ok(())
-                    });
-                handle.await.map_err(MessageError::JoinFailure)??;
-            }
+        // Dropping the step when the sequence is cancelled cancels the step's token as well
+        let Ok(msg) = execute_cmd(
+            command,
+            msg_tx.clone(),
+            cmd_tx.clone(),
+            registry.clone(),
+            name.clone(),
+            true,
+        )
+        .cancel_on_shutdown(&cancellation_token)
+        .await
+        else {
+            break;
+        };
+        let msg = msg?;
+        let abort = match (&msg, &abort_on) {
+            (Some(msg), Some(abort_on)) => abort_on(msg),
+            _ => false,
+        };
+        handle_msg::<M>(
+            msg,
+            msg_tx.clone(),
+            cmd_tx.clone(),
+            registry.clone(),
+            name.clone(),
+        )
+        .await?;
+        if abort || cancellation_token.is_cancelled() {
+            break;
         }
     }
     Ok(())
@@ -0,0 +1,278 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::{Command, registry::CommandRegistry};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QueueOrder {
+    /// Queued commands start in the order they were received.
+    #[default]
+    Fifo,
+    /// Queued commands with a higher priority start first. Commands with the same priority
+    /// start in the order they were received.
+    Priority,
+}
+
+/// Limits on how many commands may run at the same time.
+///
+/// Commands that would exceed a limit are queued until a running command finishes. Cancelling
+/// a command's name while it's queued removes it from the queue. A limit of zero is treated as
+/// one.
+#[derive(Clone, Debug, Default)]
+pub struct ConcurrencyLimits {
+    global: Option<usize>,
+    per_name: HashMap<String, usize>,
+    order: QueueOrder,
+}
+
+impl ConcurrencyLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_global_limit(self, limit: usize) -> Self {
+        Self {
+            global: Some(limit.max(1)),
+            ..self
+        }
+    }
+
+    pub fn with_limit(mut self, name: impl Into<String>, limit: usize) -> Self {
+        self.per_name.insert(name.into(), limit.max(1));
+        self
+    }
+
+    pub fn with_order(self, order: QueueOrder) -> Self {
+        Self { order, ..self }
+    }
+}
+
+#[derive(Debug)]
+struct Queued {
+    cmd: Command,
+    /// The token for the command's name when it was queued. Cancelling the name removes the
+    /// token from the registry, so the command can't look it up again when it starts.
+    cancellation_token: CancellationToken,
+}
+
+#[derive(Debug)]
+pub(crate) struct CommandScheduler {
+    limits: ConcurrencyLimits,
+    registry: Arc<CommandRegistry>,
+    running: usize,
+    running_by_name: HashMap<String, usize>,
+    queue: VecDeque<Queued>,
+}
+
+impl CommandScheduler {
+    pub(crate) fn new(limits: ConcurrencyLimits, registry: Arc<CommandRegistry>) -> Self {
+        Self {
+            limits,
+            registry,
+            running: 0,
+            running_by_name: HashMap::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Returns the command if it can start immediately, otherwise queues it.
+    ///
+    /// Queued commands are only ever blocked by a limit, so a new command that fits within the
+    /// limits doesn't need to wait behind them.
+    pub(crate) fn submit(&mut self, cmd: Command) -> Option<Command> {
+        if self.has_capacity(&cmd.name) {
+            self.start(&cmd.name);
+            Some(cmd)
+        } else {
+            let cancellation_token = self.registry.token(&cmd.name);
+            self.queue.push_back(Queued {
+                cmd,
+                cancellation_token,
+            });
+            None
+        }
+    }
+
+    pub(crate) fn finish(&mut self, name: &str) {
+        self.running = self.running.saturating_sub(1);
+        if let Some(count) = self.running_by_name.get_mut(name) {
+            *count -= 1;
+            if *count == 0 {
+                self.running_by_name.remove(name);
+            }
+        }
+    }
+
+    /// Removes the next queued command that is allowed to start.
+    pub(crate) fn next_ready(&mut self) -> Option<Command> {
+        self.remove_cancelled();
+        let mut selected: Option<usize> = None;
+        for (i, Queued { cmd, .. }) in self.queue.iter().enumerate() {
+            if !self.has_capacity(&cmd.name) {
+                continue;
+            }
+            match self.limits.order {
+                QueueOrder::Fifo => {
+                    selected = Some(i);
+                    break;
+                }
+                QueueOrder::Priority => {
+                    if selected.is_none_or(|s| cmd.priority > self.queue[s].cmd.priority) {
+                        selected = Some(i);
+                    }
+                }
+            }
+        }
+        let Queued { cmd, .. } = self.queue.remove(selected?)?;
+        self.start(&cmd.name);
+        Some(cmd)
+    }
+
+    /// Removes every queued command that hasn't been cancelled.
+    pub(crate) fn drain_queue(&mut self) -> impl Iterator<Item = Command> + '_ {
+        self.remove_cancelled();
+        self.queue.drain(..).map(|queued| queued.cmd)
+    }
+
+    fn remove_cancelled(&mut self) {
+        self.queue
+            .retain(|queued| !queued.cancellation_token.is_cancelled());
+    }
+
+    fn has_capacity(&self, name: &str) -> bool {
+        if let Some(global) = self.limits.global
+            && self.running >= global
+        {
+            return false;
+        }
+        match self.limits.per_name.get(name) {
+            Some(limit) => self.running_by_name.get(name).copied().unwrap_or(0) < *limit,
+            None => true,
+        }
+    }
+
+    fn start(&mut self, name: &str) {
+        self.running += 1;
+        *self.running_by_name.entry(name.to_owned()).or_default() += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{CommandScheduler, ConcurrencyLimits, QueueOrder};
+    use crate::{Command, Message, registry::CommandRegistry};
+
+    fn scheduler(limits: ConcurrencyLimits) -> CommandScheduler {
+        CommandScheduler::new(limits, Arc::new(CommandRegistry::default()))
+    }
+
+    fn cmd(name: &str, priority: i32) -> Command {
+        Command::simple(Message::CancelAll)
+            .with_name(name)
+            .with_priority(priority)
+    }
+
+    fn names(cmds: impl IntoIterator<Item = Command>) -> Vec<(String, i32)> {
+        cmds.into_iter()
+            .map(|cmd| (cmd.name, cmd.priority))
+            .collect()
+    }
+
+    #[test]
+    fn queues_commands_over_the_global_limit() {
+        let mut scheduler = scheduler(ConcurrencyLimits::new().with_global_limit(2));
+        assert!(scheduler.submit(cmd("a", 0)).is_some());
+        assert!(scheduler.submit(cmd("b", 0)).is_some());
+        assert!(scheduler.submit(cmd("c", 0)).is_none());
+        assert!(scheduler.submit(cmd("d", 0)).is_none());
+        assert!(scheduler.next_ready().is_none());
+
+        scheduler.finish("a");
+        assert_eq!(names(scheduler.next_ready()), [("c".to_owned(), 0)]);
+        assert!(scheduler.next_ready().is_none());
+
+        scheduler.finish("b");
+        assert_eq!(names(scheduler.next_ready()), [("d".to_owned(), 0)]);
+    }
+
+    #[test]
+    fn per_name_limits_only_block_that_name() {
+        let mut scheduler = scheduler(ConcurrencyLimits::new().with_limit("job", 1));
+        assert!(scheduler.submit(cmd("job", 0)).is_some());
+        assert!(scheduler.submit(cmd("job", 1)).is_none());
+        assert!(scheduler.submit(cmd("other", 0)).is_some());
+        assert!(scheduler.submit(cmd("other", 0)).is_some());
+
+        scheduler.finish("other");
+        assert!(scheduler.next_ready().is_none());
+        scheduler.finish("job");
+        assert_eq!(names(scheduler.next_ready()), [("job".to_owned(), 1)]);
+    }
+
+    #[test]
+    fn starts_queued_commands_in_order() {
+        let limits = ConcurrencyLimits::new().with_global_limit(1);
+        for (order, expected) in [
+            (QueueOrder::Fifo, [1, 3, 2, 3]),
+            (QueueOrder::Priority, [3, 3, 2, 1]),
+        ] {
+            let mut scheduler = scheduler(limits.clone().with_order(order));
+            assert!(scheduler.submit(cmd("running", 0)).is_some());
+            for priority in [1, 3, 2, 3] {
+                assert!(scheduler.submit(cmd("queued", priority)).is_none());
+            }
+            let mut started = Vec::new();
+            scheduler.finish("running");
+            while let Some(cmd) = scheduler.next_ready() {
+                started.push(cmd.priority);
+                scheduler.finish(&cmd.name);
+            }
+            assert_eq!(started, expected, "{order:?}");
+        }
+    }
+
+    #[test]
+    fn skips_queued_commands_that_are_still_blocked() {
+        let limits = ConcurrencyLimits::new()
+            .with_global_limit(2)
+            .with_limit("job", 1);
+        let mut scheduler = scheduler(limits);
+        assert!(scheduler.submit(cmd("job", 0)).is_some());
+        assert!(scheduler.submit(cmd("other", 0)).is_some());
+        assert!(scheduler.submit(cmd("job", 0)).is_none());
+        assert!(scheduler.submit(cmd("other", 1)).is_none());
+
+        scheduler.finish("other");
+        assert_eq!(names(scheduler.next_ready()), [("other".to_owned(), 1)]);
+        assert_eq!(names(scheduler.drain_queue()), [("job".to_owned(), 0)]);
+    }
+
+    #[test]
+    fn drops_queued_commands_when_their_name_is_cancelled() {
+        let registry = Arc::new(CommandRegistry::default());
+        let limits = ConcurrencyLimits::new().with_global_limit(1);
+        let mut scheduler = CommandScheduler::new(limits, registry.clone());
+        assert!(scheduler.submit(cmd("running", 0)).is_some());
+        assert!(scheduler.submit(cmd("job", 0)).is_none());
+        assert!(scheduler.submit(cmd("other", 0)).is_none());
+        assert!(scheduler.submit(cmd("job", 1)).is_none());
+
+        registry.cancel("job");
+        // Commands queued after the cancellation aren't affected
+        assert!(scheduler.submit(cmd("job", 2)).is_none());
+        scheduler.finish("running");
+        assert_eq!(names(scheduler.next_ready()), [("other".to_owned(), 0)]);
+        scheduler.finish("other");
+        assert_eq!(names(scheduler.next_ready()), [("job".to_owned(), 2)]);
+
+        assert!(scheduler.submit(cmd("job", 3)).is_none());
+        registry.cancel_all();
+        assert_eq!(names(scheduler.drain_queue()), []);
+    }
+}
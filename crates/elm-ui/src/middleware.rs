@@ -0,0 +1,39 @@
+use crate::{AsyncModel, Command, CustomMessage, Message, OptionalCommand};
+
+pub(crate) type SharedUpdateFn<M> =
+    fn(&mut M, &Message) -> Result<OptionalCommand, <M as AsyncModel>::Error>;
+
+/// Observes the messages passed to a model, e.g. for logging or metrics.
+///
+/// Middleware runs in the order it was added to the [`ProgramBuilder`](crate::ProgramBuilder).
+pub trait Middleware<M: AsyncModel> {
+    /// Called before `msg` is passed to [`Model::update`](crate::Model::update).
+    fn before_update(&mut self, _model: &M, _msg: &Message) {}
+
+    /// Called after the model handled a message successfully, with the command it returned.
+    ///
+    /// Since [`Model::update`](crate::Model::update) takes ownership of the message, `msg` is
+    /// only available if shared messages are enabled with
+    /// [`ProgramBuilder::with_shared_messages`](crate::ProgramBuilder::with_shared_messages).
+    fn after_update(&mut self, _model: &M, _msg: Option<&Message>, _cmd: Option<&Command>) {}
+
+    /// Called when the model returns an error, before the [`ErrorPolicy`](crate::ErrorPolicy)
+    /// is applied.
+    fn on_error(&mut self, _model: &M, _error: &M::Error) {}
+
+    /// Called the first time a custom message is received whose type has no handler registered
+    /// with [`Program::on`](crate::Program::on), before it's passed to
+    /// [`Model::update`](crate::Model::update) instead. Only called once any handlers have been
+    /// registered.
+    fn on_unhandled(&mut self, _model: &M, _msg: &CustomMessage) {}
+}
+
+/// A model that can handle messages by reference, so that middleware can observe each message
+/// after it has been handled.
+///
+/// Enable it with
+/// [`ProgramBuilder::with_shared_messages`](crate::ProgramBuilder::with_shared_messages).
+/// [`Model::update`](crate::Model::update) isn't called while it's enabled.
+pub trait SharedUpdate: AsyncModel {
+    fn update_shared(&mut self, msg: &Message) -> Result<OptionalCommand, Self::Error>;
+}
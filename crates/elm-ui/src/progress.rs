@@ -0,0 +1,216 @@
+use std::time::{Duration, Instant};
+use tokio::{
+    runtime::Handle,
+    sync::mpsc::{self, error::TrySendError},
+};
+
+use crate::Message;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub name: String,
+    pub completed: u64,
+    pub total: Option<u64>,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total {
+            Some(0) => Some(1.0),
+            Some(total) => Some((self.completed as f64 / total as f64).min(1.0)),
+            None => None,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        matches!(self.total, Some(total) if self.completed >= total)
+    }
+}
+
+/// Emits [`Message::Progress`] updates tagged with the name of the command that owns it.
+///
+/// Updates are throttled to one per interval (100ms by default). The first update and the
+/// first update that reaches the total are always emitted. Once the completion has been
+/// emitted, further updates are only emitted if the completed amount changes.
+///
+/// The reporter may be moved to another thread, e.g. by a blocking command.
+#[derive(Debug)]
+pub struct ProgressReporter {
+    name: String,
+    msg_tx: mpsc::Sender<Message>,
+    runtime: Handle,
+    interval: Duration,
+    last_sent: Option<Instant>,
+    last_sent_completed: Option<u64>,
+    completed: u64,
+    total: Option<u64>,
+    completion_sent: bool,
+}
+
+impl ProgressReporter {
+    pub(crate) fn new(
+        name: impl Into<String>,
+        msg_tx: mpsc::Sender<Message>,
+        runtime: Handle,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            msg_tx,
+            runtime,
+            interval: DEFAULT_INTERVAL,
+            last_sent: None,
+            last_sent_completed: None,
+            completed: 0,
+            total: None,
+            completion_sent: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    pub fn set_total(&mut self, total: u64) {
+        self.total = Some(total);
+        self.completion_sent = false;
+    }
+
+    /// Records the amount of completed work and emits an update if the throttle interval has
+    /// elapsed. Returns `true` if an update was emitted.
+    pub fn report(&mut self, completed: u64) -> bool {
+        self.completed = completed;
+        let complete = matches!(self.total, Some(total) if completed >= total);
+        let due = self
+            .last_sent
+            .is_none_or(|last_sent| last_sent.elapsed() >= self.interval);
+        let unchanged = self.completion_sent && self.last_sent_completed == Some(completed);
+        if complete && !self.completion_sent {
+            self.completion_sent = self.emit(true);
+            self.completion_sent
+        } else if due && !unchanged {
+            self.emit(false)
+        } else {
+            false
+        }
+    }
+
+    pub fn increment(&mut self, amount: u64) -> bool {
+        self.report(self.completed + amount)
+    }
+
+    /// Emits the current progress regardless of the throttle interval.
+    pub fn flush(&mut self) -> bool {
+        self.emit(true)
+    }
+
+    fn emit(&mut self, force: bool) -> bool {
+        let progress = Progress {
+            name: self.name.clone(),
+            completed: self.completed,
+            total: self.total,
+        };
+        match self.msg_tx.try_send(Message::Progress(progress)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(msg)) if force => {
+                // Don't drop updates that the caller explicitly asked for, but don't block the
+                // command either. The reporter may be on a thread outside the runtime.
+                let msg_tx = self.msg_tx.clone();
+                self.runtime.spawn(async move { msg_tx.send(msg).await });
+            }
+            Err(_) => return false,
+        }
+        self.last_sent = Some(Instant::now());
+        self.last_sent_completed = Some(self.completed);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use tokio::{runtime::Handle, sync::mpsc};
+
+    use super::ProgressReporter;
+    use crate::Message;
+
+    fn completed(msg: Message) -> u64 {
+        match msg {
+            Message::Progress(progress) => progress.completed,
+            msg => panic!("expected a progress message, got {msg:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn throttles_updates() {
+        let (msg_tx, mut msg_rx) = mpsc::channel(8);
+        let mut progress = ProgressReporter::new("import", msg_tx, Handle::current());
+        progress.set_interval(Duration::from_secs(60));
+        progress.set_total(10);
+
+        assert!(progress.report(1));
+        assert!(!progress.report(2));
+        assert!(!progress.increment(1));
+        assert!(progress.flush());
+
+        assert_eq!(completed(msg_rx.try_recv().unwrap()), 1);
+        assert_eq!(completed(msg_rx.try_recv().unwrap()), 3);
+        assert!(msg_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn emits_completion_once() {
+        let (msg_tx, mut msg_rx) = mpsc::channel(8);
+        let mut progress = ProgressReporter::new("import", msg_tx, Handle::current());
+        progress.set_interval(Duration::from_secs(60));
+        progress.set_total(2);
+
+        assert!(progress.report(1));
+        assert!(progress.report(2));
+        assert!(!progress.report(2));
+        assert!(!progress.increment(1));
+
+        assert_eq!(completed(msg_rx.try_recv().unwrap()), 1);
+        assert_eq!(completed(msg_rx.try_recv().unwrap()), 2);
+        assert!(msg_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn skips_unchanged_updates_after_completion() {
+        let (msg_tx, mut msg_rx) = mpsc::channel(8);
+        let mut progress = ProgressReporter::new("import", msg_tx, Handle::current());
+        progress.set_interval(Duration::ZERO);
+        progress.set_total(2);
+
+        assert!(progress.report(2));
+        assert!(!progress.report(2));
+        assert!(progress.report(3));
+
+        assert_eq!(completed(msg_rx.try_recv().unwrap()), 2);
+        assert_eq!(completed(msg_rx.try_recv().unwrap()), 3);
+        assert!(msg_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn flushes_from_a_thread_outside_the_runtime() {
+        let (msg_tx, mut msg_rx) = mpsc::channel(1);
+        let mut progress = ProgressReporter::new("import", msg_tx, Handle::current());
+        assert!(progress.report(1));
+
+        // The channel is full, so the update is sent from a task on the runtime
+        let flushed = thread::spawn(move || {
+            progress.report(2);
+            progress.flush()
+        });
+        assert!(flushed.join().unwrap());
+
+        assert_eq!(completed(msg_rx.recv().await.unwrap()), 1);
+        assert_eq!(completed(msg_rx.recv().await.unwrap()), 2);
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+use crate::Message;
+
+/// Limits how much work a single update may do before the view is rendered again.
+///
+/// By default, every pending message is processed before rendering, which lets a fast producer
+/// starve rendering indefinitely. Messages left over when the budget runs out are processed
+/// after the next render. Messages returned with [`Command::simple`](crate::Command::simple)
+/// during a frame always wait for the next one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameBudget {
+    max_messages: Option<usize>,
+    max_duration: Option<Duration>,
+    prioritize_input: bool,
+}
+
+impl FrameBudget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the number of messages processed per frame. A limit of zero is treated as one.
+    pub fn with_max_messages(self, max_messages: usize) -> Self {
+        Self {
+            max_messages: Some(max_messages.max(1)),
+            ..self
+        }
+    }
+
+    /// Limits the time spent processing messages per frame. At least one message is always
+    /// processed.
+    pub fn with_max_duration(self, max_duration: Duration) -> Self {
+        Self {
+            max_duration: Some(max_duration),
+            ..self
+        }
+    }
+
+    /// Treats input events that don't have an explicit priority as [`Priority::High`], so they
+    /// are processed before other pending messages.
+    pub fn with_prioritized_input(self, prioritize_input: bool) -> Self {
+        Self {
+            prioritize_input,
+            ..self
+        }
+    }
+
+    pub(crate) fn prioritizes_input(&self) -> bool {
+        self.prioritize_input
+    }
+
+    pub(crate) fn is_exhausted(&self, processed: usize, frame_start: Instant) -> bool {
+        self.max_messages.is_some_and(|max| processed >= max)
+            || self
+                .max_duration
+                .is_some_and(|max| frame_start.elapsed() >= max)
+    }
+}
+
+/// How urgently a queued message should be processed. Pending messages with a higher priority
+/// are processed first, and messages with the same priority are processed in the order they
+/// were received.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// A message together with the metadata used to queue it. Created with
+/// [`Message::with_priority`] and [`Message::coalesce_by`]; the model only ever receives the
+/// inner message.
+#[derive(Debug)]
+pub struct Envelope {
+    msg: Message,
+    priority: Option<Priority>,
+    coalesce_key: Option<Cow<'static, str>>,
+}
+
+impl Envelope {
+    pub(crate) fn new(msg: Message) -> Self {
+        Self {
+            msg,
+            priority: None,
+            coalesce_key: None,
+        }
+    }
+
+    pub(crate) fn set_priority(&mut self, priority: Priority) {
+        self.priority = Some(priority);
+    }
+
+    pub(crate) fn set_coalesce_key(&mut self, key: Cow<'static, str>) {
+        self.coalesce_key = Some(key);
+    }
+
+    pub fn message(&self) -> &Message {
+        &self.msg
+    }
+
+    pub fn priority(&self) -> Option<Priority> {
+        self.priority
+    }
+
+    pub fn coalesce_key(&self) -> Option<&str> {
+        self.coalesce_key.as_deref()
+    }
+
+    pub fn into_message(self) -> Message {
+        self.msg
+    }
+}
+
+#[derive(Debug)]
+struct Queued {
+    msg: Message,
+    priority: Priority,
+    coalesce_key: Option<Cow<'static, str>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct MessageQueue {
+    msg_rx: mpsc::Receiver<Message>,
+    buffer: VecDeque<Queued>,
+    prioritize_input: bool,
+    coalesce_events: bool,
+}
+
+impl MessageQueue {
+    pub(crate) fn new(msg_rx: mpsc::Receiver<Message>) -> Self {
+        Self {
+            msg_rx,
+            buffer: VecDeque::new(),
+            prioritize_input: false,
+            coalesce_events: true,
+        }
+    }
+
+    pub(crate) fn set_prioritize_input(&mut self, prioritize_input: bool) {
+        self.prioritize_input = prioritize_input;
+    }
+
+    pub(crate) fn set_coalesce_events(&mut self, coalesce_events: bool) {
+        self.coalesce_events = coalesce_events;
+    }
+
+    /// Queues a message, replacing any pending message with the same coalescing key. The
+    /// replacement keeps the position of the message it replaces.
+    pub(crate) fn push(&mut self, msg: Message) {
+        let queued = self.classify(msg);
+        if let Some(key) = &queued.coalesce_key
+            && let Some(pending) = self
+                .buffer
+                .iter_mut()
+                .find(|pending| pending.coalesce_key.as_ref() == Some(key))
+        {
+            *pending = queued;
+            return;
+        }
+        self.buffer.push_back(queued);
+    }
+
+    pub(crate) async fn recv(&mut self) -> Option<Message> {
+        if let Some(msg) = self.try_recv() {
+            return Some(msg);
+        }
+        let msg = self.msg_rx.recv().await?;
+        Some(self.classify(msg).msg)
+    }
+
+    pub(crate) fn try_recv(&mut self) -> Option<Message> {
+        // Pull in whatever is waiting in the channel so it can be coalesced and ordered by
+        // priority. The buffer is capped at the channel's capacity so producers still get
+        // backpressure.
+        while self.buffer.len() < self.msg_rx.max_capacity() {
+            match self.msg_rx.try_recv() {
+                Ok(msg) => self.push(msg),
+                Err(_) => break,
+            }
+        }
+        let mut selected: Option<usize> = None;
+        for (i, queued) in self.buffer.iter().enumerate() {
+            if selected.is_none_or(|s| queued.priority > self.buffer[s].priority) {
+                selected = Some(i);
+            }
+        }
+        self.buffer.remove(selected?).map(|queued| queued.msg)
+    }
+
+    fn classify(&self, msg: Message) -> Queued {
+        let Envelope {
+            msg,
+            priority,
+            coalesce_key,
+        } = match msg {
+            Message::Envelope(envelope) => *envelope,
+            msg => Envelope::new(msg),
+        };
+        let priority = priority.unwrap_or(if self.prioritize_input && msg.is_input() {
+            Priority::High
+        } else {
+            Priority::Normal
+        });
+        let coalesce_key = coalesce_key.or_else(|| {
+            self.coalesce_events
+                .then(|| msg.event_coalesce_key())
+                .flatten()
+                .map(Cow::Borrowed)
+        });
+        Queued {
+            msg,
+            priority,
+            coalesce_key,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::{MessageQueue, Priority};
+    use crate::Message;
+
+    fn queue() -> (mpsc::Sender<Message>, MessageQueue) {
+        let (msg_tx, msg_rx) = mpsc::channel(16);
+        (msg_tx, MessageQueue::new(msg_rx))
+    }
+
+    fn text(msg: Message) -> &'static str {
+        match msg {
+            Message::Custom(custom) => custom.downcast().unwrap(),
+            msg => panic!("unexpected message {msg:?}"),
+        }
+    }
+
+    fn drain(queue: &mut MessageQueue) -> Vec<&'static str> {
+        std::iter::from_fn(|| queue.try_recv()).map(text).collect()
+    }
+
+    #[test]
+    fn orders_by_priority_and_keeps_arrival_order() {
+        let (_msg_tx, mut queue) = queue();
+        queue.push(Message::custom("a"));
+        queue.push(Message::custom("b").with_priority(Priority::Low));
+        queue.push(Message::custom("c").with_priority(Priority::High));
+        queue.push(Message::custom("d").with_priority(Priority::Normal));
+        queue.push(Message::custom("e").with_priority(Priority::High));
+        assert_eq!(drain(&mut queue), ["c", "e", "a", "d", "b"]);
+    }
+
+    #[test]
+    fn coalesced_messages_keep_the_replaced_position() {
+        let (_msg_tx, mut queue) = queue();
+        queue.push(Message::custom("progress 1").coalesce_by("progress"));
+        queue.push(Message::custom("a"));
+        queue.push(Message::custom("other").coalesce_by("other"));
+        queue.push(Message::custom("progress 2").coalesce_by("progress"));
+        queue.push(Message::custom("b"));
+        assert_eq!(drain(&mut queue), ["progress 2", "a", "other", "b"]);
+    }
+
+    #[test]
+    fn unwraps_envelopes() {
+        let (_msg_tx, mut queue) = queue();
+        queue.push(
+            Message::custom("a")
+                .with_priority(Priority::High)
+                .coalesce_by("a"),
+        );
+        assert!(matches!(queue.try_recv(), Some(Message::Custom(_))));
+    }
+
+    #[test]
+    fn pulls_in_messages_from_the_channel() {
+        let (msg_tx, mut queue) = queue();
+        queue.push(Message::custom("a"));
+        msg_tx.try_send(Message::custom("b")).unwrap();
+        msg_tx
+            .try_send(Message::custom("c").with_priority(Priority::High))
+            .unwrap();
+        assert_eq!(drain(&mut queue), ["c", "a", "b"]);
+    }
+
+    #[cfg(feature = "crossterm")]
+    mod events {
+        use crossterm::event::{
+            Event, KeyCode, KeyEvent, KeyModifiers, MouseEvent, MouseEventKind,
+        };
+
+        use super::queue;
+        use crate::Message;
+
+        fn mouse_move(column: u16) -> Event {
+            Event::Mouse(MouseEvent {
+                kind: MouseEventKind::Moved,
+                column,
+                row: 0,
+                modifiers: KeyModifiers::NONE,
+            })
+        }
+
+        fn key() -> Event {
+            Event::Key(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE))
+        }
+
+        fn events() -> [Event; 5] {
+            [
+                Event::Resize(10, 10),
+                mouse_move(1),
+                key(),
+                Event::Resize(20, 20),
+                mouse_move(2),
+            ]
+        }
+
+        fn drain(queue: &mut super::MessageQueue) -> Vec<Event> {
+            std::iter::from_fn(|| queue.try_recv())
+                .map(|msg| match msg {
+                    Message::TermEvent(event) => event,
+                    msg => panic!("unexpected message {msg:?}"),
+                })
+                .collect()
+        }
+
+        #[test]
+        fn coalesces_resize_and_mouse_move_events() {
+            let (_msg_tx, mut queue) = queue();
+            for event in events() {
+                queue.push(Message::TermEvent(event));
+            }
+            assert_eq!(
+                drain(&mut queue),
+                [Event::Resize(20, 20), mouse_move(2), key()]
+            );
+        }
+
+        #[test]
+        fn keeps_every_event_without_coalescing() {
+            let (_msg_tx, mut queue) = queue();
+            queue.set_coalesce_events(false);
+            for event in events() {
+                queue.push(Message::TermEvent(event));
+            }
+            assert_eq!(drain(&mut queue), events());
+        }
+
+        #[test]
+        fn prioritizes_input() {
+            let (_msg_tx, mut queue) = queue();
+            queue.set_prioritize_input(true);
+            queue.push(Message::custom("a"));
+            queue.push(Message::TermEvent(key()));
+            assert!(matches!(queue.try_recv(), Some(Message::TermEvent(_))));
+            assert!(matches!(queue.try_recv(), Some(Message::Custom(_))));
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use papaya::HashMap;
+use tokio_util::sync::CancellationToken;
+
+/// Cancellation tokens for each command name.
+///
+/// Backed by a lock-free map so spawning commands never contends on a global lock.
+#[derive(Debug, Default)]
+pub(crate) struct CommandRegistry {
+    tokens: HashMap<String, CancellationToken>,
+}
+
+impl CommandRegistry {
+    /// Returns the token for `name`, registering a new one if it doesn't exist yet.
+    pub(crate) fn token(&self, name: &str) -> CancellationToken {
+        let tokens = self.tokens.pin();
+        if let Some(token) = tokens.get(name) {
+            return token.clone();
+        }
+        tokens
+            .get_or_insert_with(name.to_owned(), CancellationToken::new)
+            .clone()
+    }
+
+    /// Cancels all commands using `name`. Commands that start afterwards get a new token.
+    pub(crate) fn cancel(&self, name: &str) {
+        if let Some(token) = self.tokens.pin().remove(name) {
+            token.cancel();
+        }
+    }
+
+    /// Cancels every command. Commands that start afterwards get a new token.
+    pub(crate) fn cancel_all(&self) {
+        let tokens = self.tokens.pin();
+        for name in tokens.keys() {
+            if let Some(token) = tokens.remove(name) {
+                token.cancel();
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::{error::Error, fmt::Display};
+
+use crate::{CommandInfo, MessageError};
+
+/// A task that was still tracked by the runtime when the program shut down.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ShutdownTask {
+    EventHandler,
+    MessageHandler,
+    Command(CommandInfo),
+    /// A message waiting for space in the message queue.
+    PendingMessage,
+}
+
+impl ShutdownTask {
+    /// Runtime tasks either run a command or only deliver a message.
+    pub(crate) fn from_command(command: Option<CommandInfo>) -> Self {
+        match command {
+            Some(command) => Self::Command(command),
+            None => Self::PendingMessage,
+        }
+    }
+}
+
+impl Display for ShutdownTask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EventHandler => write!(f, "event handler"),
+            Self::MessageHandler => write!(f, "message handler"),
+            Self::Command(command) => write!(f, "{command}"),
+            Self::PendingMessage => write!(f, "pending message"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TaskFailure {
+    pub task: ShutdownTask,
+    pub error: MessageError,
+}
+
+/// Tasks that didn't finish cleanly while the program was shutting down.
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    /// Tasks that returned an error or panicked.
+    pub failed: Vec<TaskFailure>,
+    /// Tasks that were aborted because they didn't finish before the shutdown timeout, along
+    /// with any tasks they spawned. Blocking commands can't be interrupted, so their closures
+    /// keep running in the background until they return.
+    pub aborted: Vec<ShutdownTask>,
+}
+
+impl ShutdownReport {
+    pub fn has_failures(&self) -> bool {
+        !self.failed.is_empty()
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.failed.is_empty() && self.aborted.is_empty()
+    }
+
+    pub(crate) fn merge(&mut self, other: ShutdownReport) {
+        self.failed.extend(other.failed);
+        self.aborted.extend(other.aborted);
+    }
+}
+
+impl Display for ShutdownReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} task(s) failed and {} task(s) were aborted during shutdown",
+            self.failed.len(),
+            self.aborted.len()
+        )?;
+        for failure in &self.failed {
+            write!(f, "; {} failed: {}", failure.task, failure.error)?;
+            let mut source = failure.error.source();
+            while let Some(error) = source {
+                write!(f, ": {error}")?;
+                source = error.source();
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use std::any::Any;
+
+use crate::Message;
+
+/// A custom message type that can be converted to and from a [`Message::Custom`].
+///
+/// Usually implemented with `#[derive(TypedMessage)]`, which also implements
+/// `From<T> for Message`. All of the methods have default implementations.
+///
+/// ```
+/// use elm_ui::{Message, TypedMessage};
+///
+/// enum Counter {
+///     Increment,
+///     Decrement,
+/// }
+///
+/// impl TypedMessage for Counter {}
+///
+/// fn update(val: &mut i32, msg: Message) {
+///     match Counter::from_message(msg) {
+///         Ok(Counter::Increment) => *val += 1,
+///         Ok(Counter::Decrement) => *val -= 1,
+///         Err(_other) => {}
+///     }
+/// }
+///
+/// let mut val = 0;
+/// update(&mut val, Counter::Increment.into_message());
+/// assert_eq!(val, 1);
+/// ```
+pub trait TypedMessage: Any + Send + Sized {
+    fn into_message(self) -> Message {
+        Message::custom(self)
+    }
+
+    /// Takes the value out of `msg` if it holds this type, otherwise returns `msg` unchanged
+    /// so it can be matched against other types.
+    fn from_message(msg: Message) -> Result<Self, Message> {
+        match msg {
+            Message::Custom(custom) => custom.downcast().map_err(Message::Custom),
+            msg => Err(msg),
+        }
+    }
+
+    /// Borrows the value in `msg` if it holds this type.
+    fn from_message_ref(msg: &Message) -> Option<&Self> {
+        match msg {
+            Message::Custom(custom) => custom.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Several [`TypedMessage`] types that can be handled with a single `match`, without nesting a
+/// `from_message` call for each type.
+///
+/// Usually implemented with `#[derive(MessageSet)]` on an enum with one variant for each type,
+/// which also implements `From<T> for Message`.
+///
+/// ```
+/// use elm_ui::{Message, MessageSet, TypedMessage};
+///
+/// struct Tick(u32);
+/// impl TypedMessage for Tick {}
+///
+/// struct Reset;
+/// impl TypedMessage for Reset {}
+///
+/// enum AppMessage {
+///     Tick(Tick),
+///     Reset(Reset),
+/// }
+///
+/// impl MessageSet for AppMessage {
+///     fn from_message(msg: Message) -> Result<Self, Message> {
+///         let msg = match Tick::from_message(msg) {
+///             Ok(tick) => return Ok(Self::Tick(tick)),
+///             Err(msg) => msg,
+///         };
+///         Reset::from_message(msg).map(Self::Reset)
+///     }
+///
+///     fn into_message(self) -> Message {
+///         match self {
+///             Self::Tick(tick) => tick.into_message(),
+///             Self::Reset(reset) => reset.into_message(),
+///         }
+///     }
+/// }
+///
+/// match AppMessage::from_message(Tick(1).into_message()) {
+///     Ok(AppMessage::Tick(Tick(n))) => assert_eq!(n, 1),
+///     Ok(AppMessage::Reset(Reset)) => unreachable!(),
+///     Err(_other) => unreachable!(),
+/// }
+/// ```
+pub trait MessageSet: Sized {
+    /// Takes the value out of `msg` if it holds one of the types in the set, otherwise returns
+    /// `msg` unchanged.
+    fn from_message(msg: Message) -> Result<Self, Message>;
+
+    fn into_message(self) -> Message;
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use std::io;
+
+use elm_ui::{AsyncModel, Command, Message, OptionalCommand};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+use common::{builder, finish};
+
+/// Counts to `target` one message at a time, writing each count to a stream.
+#[derive(Debug)]
+struct Counter {
+    count: u32,
+    target: u32,
+}
+
+impl AsyncModel for Counter {
+    type Writer = DuplexStream;
+    type Error = io::Error;
+    type Flags = ();
+
+    async fn init(&mut self, _flags: Self::Flags) -> Result<OptionalCommand, Self::Error> {
+        tokio::task::yield_now().await;
+        Ok(Some(Command::simple(Message::custom(1u32))))
+    }
+
+    async fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error> {
+        let Message::Custom(custom) = msg else {
+            return Ok(None);
+        };
+        let Ok(step) = custom.downcast::<u32>() else {
+            return Ok(None);
+        };
+        tokio::task::yield_now().await;
+        self.count += step;
+        if self.count == self.target {
+            return Ok(Some(Command::quit()));
+        }
+        Ok(Some(Command::simple(Message::custom(1u32))))
+    }
+
+    async fn view(&self, writer: &mut Self::Writer) -> Result<(), Self::Error> {
+        writer
+            .write_all(format!("{}\n", self.count).as_bytes())
+            .await
+    }
+}
+
+#[tokio::test]
+async fn async_models_are_driven_by_run() {
+    let (mut reader, mut writer) = tokio::io::duplex(1024);
+    let exit = finish(
+        builder(
+            Counter {
+                count: 0,
+                target: 3,
+            },
+            (),
+        )
+        .build()
+        .run(&mut writer),
+    )
+    .await
+    .unwrap();
+    assert_eq!(exit.model.count, 3);
+
+    drop(writer);
+    let mut output = String::new();
+    reader.read_to_string(&mut output).await.unwrap();
+    // The initial render, one for each count, and the final render before quitting
+    assert_eq!(output, "0\n1\n2\n3\n3\n");
+}
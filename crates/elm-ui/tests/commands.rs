@@ -0,0 +1,389 @@
+mod common;
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use elm_ui::{Command, ConcurrencyLimits, Message, ProgramError, QueueOrder, Sequence};
+use futures::{StreamExt, stream};
+
+use common::{Recorder, builder, entry, finish, log};
+
+/// Sleeps briefly and records its name, tracking the most commands seen running at once.
+fn job(name: &'static str, running: &Arc<AtomicUsize>, max: &Arc<AtomicUsize>) -> Command {
+    let running = running.clone();
+    let max = max.clone();
+    Command::new_async(move |_, _| async move {
+        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+        max.fetch_max(now, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        running.fetch_sub(1, Ordering::SeqCst);
+        Some(entry(name))
+    })
+}
+
+#[tokio::test]
+async fn global_limit_runs_commands_one_at_a_time() {
+    let running = Arc::new(AtomicUsize::new(0));
+    let max = Arc::new(AtomicUsize::new(0));
+    let cmds = ["a", "b", "c", "d"].map(|name| job(name, &running, &max));
+    let exit = finish(
+        builder(Recorder::new(4), cmds.into())
+            .with_concurrency_limits(ConcurrencyLimits::new().with_global_limit(1))
+            .build()
+            .run(&mut ()),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(max.load(Ordering::SeqCst), 1);
+    assert_eq!(log(exit), ["a", "b", "c", "d"]);
+}
+
+#[tokio::test]
+async fn priority_order_starts_higher_priorities_first() {
+    let running = Arc::new(AtomicUsize::new(0));
+    let max = Arc::new(AtomicUsize::new(0));
+    let cmds = vec![
+        job("first", &running, &max).with_name("job"),
+        job("low", &running, &max)
+            .with_name("job")
+            .with_priority(-1),
+        job("high", &running, &max)
+            .with_name("job")
+            .with_priority(5),
+        job("normal", &running, &max).with_name("job"),
+        job("unlimited", &running, &max),
+    ];
+    let limits = ConcurrencyLimits::new()
+        .with_limit("job", 1)
+        .with_order(QueueOrder::Priority);
+    let exit = finish(
+        builder(Recorder::new(5), cmds)
+            .with_concurrency_limits(limits)
+            .build()
+            .run(&mut ()),
+    )
+    .await
+    .unwrap();
+
+    let log = log(exit);
+    let jobs: Vec<_> = log.iter().filter(|name| *name != "unlimited").collect();
+    assert_eq!(jobs, ["first", "high", "normal", "low"]);
+    // Only the named commands are limited
+    assert_eq!(max.load(Ordering::SeqCst), 2);
+}
+
+/// Waits for its cancellation token, recording whether it was cancelled.
+fn cancellable(name: &'static str) -> Command {
+    Command::new_async(move |_, cancellation_token| async move {
+        let cancelled = tokio::select! {
+            _ = cancellation_token.cancelled() => true,
+            _ = tokio::time::sleep(Duration::from_millis(300)) => false,
+        };
+        Some(entry(format!("{name} cancelled={cancelled}")))
+    })
+}
+
+fn cancel_after(delay: Duration, name: &'static str) -> Command {
+    send_after(delay, move || Message::Cancel(name.to_owned()))
+}
+
+#[tokio::test]
+async fn batch_children_inherit_the_parent_name() {
+    let parent = Command::new_async(|_, _| async {
+        Some(Message::Batch(vec![
+            cancellable("child"),
+            cancellable("named child").with_name("other"),
+        ]))
+    })
+    .with_name("parent");
+    let exit = finish(
+        builder(
+            Recorder::new(2),
+            vec![parent, cancel_after(Duration::from_millis(20), "parent")],
+        )
+        .build()
+        .run(&mut ()),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        log(exit),
+        ["child cancelled=true", "named child cancelled=false"]
+    );
+}
+
+fn send_after(delay: Duration, msg: impl FnOnce() -> Message + Send + 'static) -> Command {
+    Command::new_async(move |_, _| async move {
+        tokio::time::sleep(delay).await;
+        Some(msg())
+    })
+}
+
+#[tokio::test]
+async fn cancel_removes_queued_commands() {
+    let cmds = vec![
+        cancellable("job 1").with_name("job"),
+        cancellable("job 2").with_name("job"),
+        cancellable("other"),
+        cancel_after(Duration::from_millis(20), "job"),
+        send_after(Duration::from_millis(400), || entry("done")),
+    ];
+    let exit = finish(
+        builder(Recorder::new(3), cmds)
+            .with_concurrency_limits(ConcurrencyLimits::new().with_limit("job", 1))
+            .build()
+            .run(&mut ()),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        log(exit),
+        ["job 1 cancelled=true", "other cancelled=false", "done"]
+    );
+}
+
+#[tokio::test]
+async fn cancel_all_clears_the_queue() {
+    let cmds = vec![
+        cancellable("job 1").with_name("job"),
+        cancellable("job 2").with_name("job"),
+        cancellable("job 3").with_name("job"),
+        send_after(Duration::from_millis(20), || Message::CancelAll),
+        send_after(Duration::from_millis(400), || entry("done")),
+    ];
+    let exit = finish(
+        builder(Recorder::new(2), cmds)
+            .with_concurrency_limits(ConcurrencyLimits::new().with_limit("job", 1))
+            .build()
+            .run(&mut ()),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(log(exit), ["job 1 cancelled=true", "done"]);
+}
+
+/// The string recorded by a message from [`entry`], or `-` if there's no message.
+fn text(msg: Option<Message>) -> String {
+    match msg {
+        Some(Message::Custom(custom)) => custom.downcast::<String>().unwrap(),
+        Some(msg) => panic!("unexpected message {msg:?}"),
+        None => "-".to_owned(),
+    }
+}
+
+#[tokio::test]
+async fn race_cancels_only_the_losers() {
+    // A loser is dropped as soon as the race is decided, so it reports its cancellation from a
+    // separate task
+    let loser = Command::new_async(|cmd_tx, cancellation_token| async move {
+        tokio::spawn(async move {
+            cancellation_token.cancelled().await;
+            cmd_tx
+                .send(Command::simple(entry("loser cancelled")))
+                .await
+                .unwrap();
+        });
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        Some(entry("loser finished"))
+    });
+    let race = Command::race([
+        loser,
+        send_after(Duration::from_millis(10), || entry("winner")),
+    ])
+    .with_name("job");
+    let cmds = vec![race, cancellable("sibling").with_name("job")];
+    let exit = finish(builder(Recorder::new(3), cmds).build().run(&mut ()))
+        .await
+        .unwrap();
+
+    let mut log = log(exit);
+    assert_eq!(log.pop().unwrap(), "sibling cancelled=false");
+    log.sort();
+    assert_eq!(log, ["loser cancelled", "winner"]);
+}
+
+#[tokio::test]
+async fn join_returns_results_in_order() {
+    let join = Command::join(
+        [
+            send_after(Duration::from_millis(30), || entry("slow")),
+            Command::new_async(|_, _| async { None }),
+            send_after(Duration::from_millis(10), || entry("fast")),
+        ],
+        |msgs| entry(msgs.into_iter().map(text).collect::<Vec<_>>().join(" ")),
+    );
+    let exit = finish(builder(Recorder::new(1), vec![join]).build().run(&mut ()))
+        .await
+        .unwrap();
+
+    assert_eq!(log(exit), ["slow - fast"]);
+}
+
+#[tokio::test]
+async fn join_fails_if_a_command_fails() {
+    let join = Command::join(
+        [
+            send_after(Duration::from_millis(10), || entry("ok")),
+            Command::new_blocking(|_, _| panic!("failed")),
+        ],
+        |_| entry("joined"),
+    )
+    .with_name("join");
+    let res = finish(builder(Recorder::new(1), vec![join]).build().run(&mut ())).await;
+
+    let Err(ProgramError::MessageFailure(error)) = res else {
+        panic!("expected the join to fail");
+    };
+    assert!(error.is_panic());
+    assert_eq!(error.command().unwrap().name, "join");
+}
+
+#[tokio::test]
+async fn then_runs_after_the_first_command() {
+    let then = Command::simple(entry("first"))
+        .then(|msg| Command::simple(entry(format!("after {}", text(Some(msg))))));
+    let exit = finish(builder(Recorder::new(1), vec![then]).build().run(&mut ()))
+        .await
+        .unwrap();
+
+    assert_eq!(log(exit), ["after first"]);
+}
+
+#[tokio::test]
+async fn then_stops_without_a_message() {
+    let called = Arc::new(AtomicBool::new(false));
+    let then = Command::new_async(|_, _| async { None }).then({
+        let called = called.clone();
+        move |_| {
+            called.store(true, Ordering::SeqCst);
+            Command::simple(entry("then"))
+        }
+    });
+    let cmds = vec![
+        then,
+        send_after(Duration::from_millis(20), || entry("done")),
+    ];
+    let exit = finish(builder(Recorder::new(1), cmds).build().run(&mut ()))
+        .await
+        .unwrap();
+
+    assert_eq!(log(exit), ["done"]);
+    assert!(!called.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn composite_children_inherit_the_composite_name() {
+    let join = Command::join(
+        [
+            cancellable("child"),
+            cancellable("named child").with_name("other"),
+        ],
+        |msgs| entry(msgs.into_iter().map(text).collect::<Vec<_>>().join(", ")),
+    )
+    .with_name("composite");
+    let race = Command::race([cancellable("race child")])
+        .map(|msg| entry(format!("mapped {}", text(Some(msg)))))
+        .with_name("composite");
+    let cmds = vec![
+        join,
+        race,
+        cancel_after(Duration::from_millis(20), "composite"),
+    ];
+    let exit = finish(builder(Recorder::new(2), cmds).build().run(&mut ()))
+        .await
+        .unwrap();
+
+    let mut log = log(exit);
+    log.sort();
+    assert_eq!(
+        log,
+        [
+            "child cancelled=true, named child cancelled=false",
+            "mapped race child cancelled=true",
+        ]
+    );
+}
+
+/// Runs `sequence` from a command named `seq`.
+fn sequence(sequence: Sequence) -> Command {
+    Command::new_async(|_, _| async { Some(Message::Sequence(sequence)) }).with_name("seq")
+}
+
+#[tokio::test]
+async fn sequence_steps_finish_before_the_next_step() {
+    let nested = Sequence::new([
+        send_after(Duration::from_millis(20), || entry("nested 1")),
+        Command::simple(entry("nested 2")),
+    ]);
+    let stream = stream::iter(["stream 1", "stream 2"]).then(|name| async move {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        entry(name)
+    });
+    let steps = Sequence::new([
+        send_after(Duration::ZERO, || Message::Sequence(nested)),
+        send_after(Duration::ZERO, || Message::Stream(Box::pin(stream))),
+        Command::simple(entry("last")),
+    ]);
+    let exit = finish(
+        builder(Recorder::new(5), vec![sequence(steps)])
+            .build()
+            .run(&mut ()),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        log(exit),
+        ["nested 1", "nested 2", "stream 1", "stream 2", "last"]
+    );
+}
+
+#[tokio::test]
+async fn cancelling_a_sequence_skips_the_remaining_steps() {
+    let steps = Sequence::new([
+        Command::simple(entry("step 1")),
+        send_after(Duration::from_millis(50), || entry("step 2")),
+        Command::simple(entry("step 3")),
+    ]);
+    let cmds = vec![
+        sequence(steps),
+        cancel_after(Duration::from_millis(20), "seq"),
+        send_after(Duration::from_millis(200), || entry("done")),
+    ];
+    let exit = finish(builder(Recorder::new(2), cmds).build().run(&mut ()))
+        .await
+        .unwrap();
+
+    assert_eq!(log(exit), ["step 1", "done"]);
+}
+
+#[tokio::test]
+async fn abort_on_delivers_the_matching_message_and_stops() {
+    let steps = Sequence::new([
+        Command::simple(entry("ok 1")),
+        Command::simple(entry("failed")),
+        Command::simple(entry("ok 2")),
+    ])
+    .abort_on(|msg| {
+        matches!(msg, Message::Custom(custom)
+            if custom.downcast_ref::<String>().is_some_and(|entry| entry == "failed"))
+    });
+    let cmds = vec![
+        sequence(steps),
+        send_after(Duration::from_millis(50), || entry("done")),
+    ];
+    let exit = finish(builder(Recorder::new(3), cmds).build().run(&mut ()))
+        .await
+        .unwrap();
+
+    assert_eq!(log(exit), ["ok 1", "failed", "done"]);
+}
@@ -0,0 +1,72 @@
+// Each test binary only uses some of these
+#![allow(dead_code)]
+
+use std::{future::Future, io, time::Duration};
+
+use elm_ui::{AsyncModel, Command, Message, Model, OptionalCommand, ProgramBuilder, ProgramExit};
+
+/// Records the string messages it receives and quits once it has `expected` of them.
+#[derive(Debug)]
+pub struct Recorder {
+    pub log: Vec<String>,
+    expected: usize,
+}
+
+impl Recorder {
+    pub fn new(expected: usize) -> Self {
+        Self {
+            log: Vec::new(),
+            expected,
+        }
+    }
+}
+
+impl Model for Recorder {
+    type Writer = ();
+    type Error = io::Error;
+    /// Commands to run on startup.
+    type Flags = Vec<Command>;
+
+    fn init(&mut self, cmds: Self::Flags) -> Result<OptionalCommand, Self::Error> {
+        Ok(Some(Command::simple(Message::Batch(cmds))))
+    }
+
+    fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error> {
+        if let Message::Custom(custom) = msg
+            && let Ok(entry) = custom.downcast::<String>()
+        {
+            self.log.push(entry);
+            if self.log.len() == self.expected {
+                return Ok(Some(Command::quit()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn view(&self, _writer: &mut Self::Writer) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+pub fn entry(entry: impl Into<String>) -> Message {
+    Message::custom(entry.into())
+}
+
+/// A builder that doesn't read terminal events, since tests don't run in a terminal.
+pub fn builder<M: AsyncModel>(model: M, flags: M::Flags) -> ProgramBuilder<M> {
+    let builder = ProgramBuilder::new(model, flags);
+    #[cfg(feature = "crossterm")]
+    let builder = builder.with_spawn_event_handler(false);
+    builder
+}
+
+/// Fails the test instead of hanging if the program doesn't finish.
+pub async fn finish<T>(fut: impl Future<Output = T>) -> T {
+    tokio::time::timeout(Duration::from_secs(5), fut)
+        .await
+        .expect("the program didn't finish in time")
+}
+
+pub fn log(exit: ProgramExit<Recorder>) -> Vec<String> {
+    exit.model.log
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use elm_ui::{QueryError, QuitRequest};
+
+use common::{Recorder, builder, entry, finish};
+
+#[tokio::test]
+async fn queries_see_the_model_while_running() {
+    let program = builder(Recorder::new(10), Vec::new()).build();
+    let handle = program.handle();
+    let run = tokio::spawn(async move { program.run(&mut ()).await });
+
+    assert_eq!(handle.query(|model| model.log.len()).await.unwrap(), 0);
+    handle.send(entry("a")).await.unwrap();
+    let log = finish(async {
+        loop {
+            let log = handle.query(|model| model.log.clone()).await.unwrap();
+            if !log.is_empty() {
+                return log;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await;
+    assert_eq!(log, ["a"]);
+
+    handle.try_quit(QuitRequest::new().with_code(3)).unwrap();
+    let exit = finish(run).await.unwrap().unwrap();
+    assert_eq!(exit.code, 3);
+}
+
+#[tokio::test]
+async fn queries_fail_after_the_program_stops() {
+    let program = builder(Recorder::new(1), Vec::new()).build();
+    let handle = program.handle();
+    handle.send(entry("a")).await.unwrap();
+    finish(program.run(&mut ())).await.unwrap();
+
+    assert!(matches!(
+        handle.query(|model| model.log.len()).await,
+        Err(QueryError)
+    ));
+}
+
+#[test]
+fn blocking_queries_from_another_thread() {
+    let program = builder(Recorder::new(10), Vec::new()).build();
+    let handle = program.handle();
+    let run = thread::spawn(move || program.run_blocking(&mut ()));
+
+    assert_eq!(handle.query_blocking(|model| model.log.len()).unwrap(), 0);
+    handle.try_quit(QuitRequest::new()).unwrap();
+    run.join().unwrap().unwrap();
+    assert!(matches!(
+        handle.query_blocking(|model| model.log.len()),
+        Err(QueryError)
+    ));
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use elm_ui::{Command, CustomMessage, Message, Middleware};
+
+use common::{Recorder, builder, entry, finish, log};
+
+/// Records the type names reported as unhandled.
+struct Unhandled(Arc<Mutex<Vec<&'static str>>>);
+
+impl Middleware<Recorder> for Unhandled {
+    fn on_unhandled(&mut self, _model: &Recorder, msg: &CustomMessage) {
+        self.0.lock().unwrap().push(msg.type_name());
+    }
+}
+
+#[tokio::test]
+async fn handlers_take_their_type_and_report_other_types_once() {
+    let unhandled = Arc::new(Mutex::new(Vec::new()));
+    let cmds = vec![
+        Command::simple(Message::custom(1u32)),
+        Command::simple(entry("a")),
+        Command::simple(Message::custom(2u32)),
+        Command::simple(entry("b")),
+    ];
+    let mut program = builder(Recorder::new(4), cmds)
+        .with_middleware(Unhandled(unhandled.clone()))
+        .build();
+    program.on(|model: &mut Recorder, n: u32| {
+        model.log.push(format!("tick {n}"));
+        Ok(None)
+    });
+    let exit = finish(program.run(&mut ())).await.unwrap();
+
+    assert_eq!(log(exit), ["tick 1", "a", "tick 2", "b"]);
+    assert_eq!(
+        *unhandled.lock().unwrap(),
+        [std::any::type_name::<String>()]
+    );
+}
+
+#[tokio::test]
+async fn nothing_is_unhandled_without_handlers() {
+    let unhandled = Arc::new(Mutex::new(Vec::new()));
+    let program = builder(Recorder::new(1), vec![Command::simple(entry("a"))])
+        .with_middleware(Unhandled(unhandled.clone()))
+        .build();
+    let exit = finish(program.run(&mut ())).await.unwrap();
+
+    assert_eq!(log(exit), ["a"]);
+    assert!(unhandled.lock().unwrap().is_empty());
+}
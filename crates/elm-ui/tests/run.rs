@@ -0,0 +1,50 @@
+mod common;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use elm_ui::{Command, Program, QuitBehavior};
+
+use common::{Recorder, builder, entry, log};
+
+/// A program that needs a working runtime to finish, since its only message comes from a timer.
+fn timer_program() -> Program<Recorder> {
+    let timer = Command::new_async(|_, _| async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        Some(entry("tick"))
+    });
+    builder(Recorder::new(1), vec![timer]).build()
+}
+
+#[test]
+fn run_blocking_without_a_runtime() {
+    let exit = timer_program().run_blocking(&mut ()).unwrap();
+    assert_eq!(log(exit), ["tick"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_blocking_inside_a_multi_threaded_runtime() {
+    let exit = timer_program().run_blocking(&mut ()).unwrap();
+    assert_eq!(log(exit), ["tick"]);
+}
+
+#[tokio::test]
+async fn run_blocking_inside_a_current_thread_runtime() {
+    let exit = timer_program().run_blocking(&mut ()).unwrap();
+    assert_eq!(log(exit), ["tick"]);
+}
+
+#[test]
+fn step_runs_async_commands_on_its_own_runtime() {
+    let mut program = timer_program();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    // Like a GUI toolkit's repeating timer
+    while program.step(&mut ()).unwrap() == QuitBehavior::Continue {
+        assert!(Instant::now() < deadline, "the program didn't quit in time");
+        thread::sleep(Duration::from_millis(1));
+    }
+    let exit = program.shutdown_blocking().unwrap();
+    assert_eq!(log(exit), ["tick"]);
+}
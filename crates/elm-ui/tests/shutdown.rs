@@ -0,0 +1,184 @@
+mod common;
+
+use std::{
+    io,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::Poll,
+    time::Duration,
+};
+
+use elm_ui::{Command, Message, Model, OptionalCommand, ShutdownTask, future_ext::FutureExt as _};
+use futures::{future, stream};
+
+use common::{builder, finish};
+
+/// Runs its startup commands and quits right away.
+#[derive(Debug)]
+struct App {
+    on_shutdown: Option<Command>,
+}
+
+impl App {
+    fn new(on_shutdown: Option<Command>) -> Self {
+        Self { on_shutdown }
+    }
+}
+
+impl Model for App {
+    type Writer = ();
+    type Error = io::Error;
+    type Flags = Vec<Command>;
+
+    fn init(&mut self, mut cmds: Self::Flags) -> Result<OptionalCommand, Self::Error> {
+        cmds.push(Command::quit());
+        Ok(Some(Command::simple(Message::Batch(cmds))))
+    }
+
+    fn update(&mut self, _msg: Message) -> Result<OptionalCommand, Self::Error> {
+        Ok(None)
+    }
+
+    fn view(&self, _writer: &mut Self::Writer) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn on_shutdown(&mut self) -> Result<OptionalCommand, Self::Error> {
+        Ok(self.on_shutdown.take())
+    }
+}
+
+struct Saved;
+
+#[tokio::test]
+async fn ready_shutdown_commands_dont_block_shutdown() {
+    for _ in 0..20 {
+        let app = App::new(Some(Command::simple(Message::custom(Saved))));
+        let exit = finish(builder(app, Vec::new()).build().run(&mut ()))
+            .await
+            .unwrap();
+        assert!(exit.report.is_clean());
+    }
+}
+
+#[tokio::test]
+async fn shutdown_commands_run_to_completion() {
+    let saved = Arc::new(AtomicBool::new(false));
+    let app = App::new(Some(Command::new_async({
+        let saved = saved.clone();
+        |_, _| async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            saved.store(true, Ordering::SeqCst);
+            None
+        }
+    })));
+    let exit = finish(builder(app, Vec::new()).build().run(&mut ()))
+        .await
+        .unwrap();
+
+    assert!(saved.load(Ordering::SeqCst));
+    assert!(exit.report.is_clean());
+}
+
+#[tokio::test]
+async fn cancelled_commands_finish_before_the_timeout() {
+    let cooperative = Command::new_async(|_, cancellation_token| async move {
+        let _ = future::pending::<()>()
+            .cancel_on_shutdown(&cancellation_token)
+            .await;
+        None
+    });
+    let exit = finish(
+        builder(App::new(None), vec![cooperative])
+            .with_shutdown_timeout(Duration::from_secs(5))
+            .build()
+            .run(&mut ()),
+    )
+    .await
+    .unwrap();
+
+    assert!(exit.report.is_clean());
+}
+
+#[tokio::test]
+async fn commands_are_aborted_after_the_timeout() {
+    let stuck = Command::new_async(|_, _| async {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        None
+    })
+    .with_name("stuck");
+    let info = stuck.info();
+    let exit = finish(
+        builder(App::new(None), vec![stuck])
+            .with_shutdown_timeout(Duration::from_millis(50))
+            .build()
+            .run(&mut ()),
+    )
+    .await
+    .unwrap();
+
+    assert!(exit.report.failed.is_empty());
+    assert_eq!(exit.report.aborted, [ShutdownTask::Command(info)]);
+}
+
+#[tokio::test]
+async fn failed_shutdown_commands_are_reported() {
+    let app = App::new(Some(
+        Command::new_async(|_, _| async { panic!("failed to save") }).with_name("save"),
+    ));
+    let exit = finish(
+        builder(app, vec![Command::quit_with_code(4)])
+            .build()
+            .run(&mut ()),
+    )
+    .await
+    .unwrap();
+
+    // The exit code is kept so the caller can still exit with it
+    assert_eq!(exit.code, 4);
+    let report = exit.report;
+    assert!(report.aborted.is_empty());
+    assert_eq!(report.failed.len(), 1);
+    let failure = &report.failed[0];
+    assert!(failure.error.is_panic());
+    assert!(matches!(&failure.task, ShutdownTask::Command(info) if info.name == "save"));
+}
+
+/// Sets a flag when dropped.
+struct DropFlag(Arc<AtomicBool>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[tokio::test]
+async fn tasks_spawned_by_aborted_commands_are_aborted() {
+    let dropped = Arc::new(AtomicBool::new(false));
+    let guard = DropFlag(dropped.clone());
+    // The stream ignores cancellation, so it only stops if its task is aborted
+    let stream = stream::poll_fn(move |_| {
+        let _guard = &guard;
+        Poll::<Option<Message>>::Pending
+    });
+    let cmd = Command::new_async(|_, _| async move { Some(Message::Stream(Box::pin(stream))) });
+    let exit = finish(
+        builder(App::new(None), vec![cmd])
+            .with_shutdown_timeout(Duration::from_millis(50))
+            .build()
+            .run(&mut ()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(exit.report.aborted.len(), 1);
+
+    finish(async {
+        while !dropped.load(Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await;
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use std::{io, thread, time::Duration};
+
+use elm_ui::{Command, FrameBudget, Message, Model, OptionalCommand, Program};
+
+use common::{builder, finish};
+
+/// Counts down by answering each message with a ready message. Renders are counted by the
+/// writer.
+#[derive(Debug)]
+struct Countdown;
+
+impl Model for Countdown {
+    type Writer = usize;
+    type Error = io::Error;
+    type Flags = u32;
+
+    fn init(&mut self, from: Self::Flags) -> Result<OptionalCommand, Self::Error> {
+        Ok(Some(Command::simple(Message::custom(from))))
+    }
+
+    fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error> {
+        let Message::Custom(custom) = msg else {
+            return Ok(None);
+        };
+        match custom.downcast::<u32>() {
+            Ok(0) => Ok(Some(Command::quit())),
+            Ok(n) => Ok(Some(Command::simple(Message::custom(n - 1)))),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn view(&self, renders: &mut Self::Writer) -> Result<(), Self::Error> {
+        *renders += 1;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn ready_messages_are_processed_in_the_next_frame() {
+    let mut renders = 0;
+    finish(builder(Countdown, 100).build().run(&mut renders))
+        .await
+        .unwrap();
+    // The initial render and one for each of the 101 messages and the quit message
+    assert_eq!(renders, 103);
+}
+
+/// Records the messages it receives and quits once it has `expected` of them, taking `delay`
+/// for each one. Renders are counted by the writer.
+#[derive(Debug)]
+struct Flood {
+    log: Vec<String>,
+    expected: usize,
+    delay: Duration,
+}
+
+impl Flood {
+    fn new(expected: usize) -> Self {
+        Self {
+            log: Vec::new(),
+            expected,
+            delay: Duration::ZERO,
+        }
+    }
+}
+
+impl Model for Flood {
+    type Writer = usize;
+    type Error = io::Error;
+    type Flags = ();
+
+    fn init(&mut self, _flags: Self::Flags) -> Result<OptionalCommand, Self::Error> {
+        Ok(None)
+    }
+
+    fn update(&mut self, msg: Message) -> Result<OptionalCommand, Self::Error> {
+        let entry = match msg {
+            Message::Custom(custom) => custom.downcast::<String>().unwrap(),
+            #[cfg(feature = "crossterm")]
+            Message::TermEvent(_) => "input".to_owned(),
+            _ => return Ok(None),
+        };
+        thread::sleep(self.delay);
+        self.log.push(entry);
+        if self.log.len() == self.expected {
+            return Ok(Some(Command::quit()));
+        }
+        Ok(None)
+    }
+
+    fn view(&self, renders: &mut Self::Writer) -> Result<(), Self::Error> {
+        *renders += 1;
+        Ok(())
+    }
+}
+
+/// Fills the message queue before the program starts, so every message is pending at once.
+fn flood(program: &Program<Flood>, count: usize) {
+    let msg_tx = program.msg_tx();
+    for i in 0..count {
+        msg_tx.try_send(Message::custom(i.to_string())).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn pending_messages_share_a_frame_without_a_budget() {
+    let program = builder(Flood::new(30), ()).build();
+    flood(&program, 30);
+    let mut renders = 0;
+    finish(program.run(&mut renders)).await.unwrap();
+    // The initial render, one for all 30 messages, and one for the quit message
+    assert_eq!(renders, 3);
+}
+
+#[tokio::test]
+async fn message_budget_renders_between_batches() {
+    let program = builder(Flood::new(30), ())
+        .with_frame_budget(FrameBudget::new().with_max_messages(10))
+        .build();
+    flood(&program, 30);
+    let mut renders = 0;
+    let exit = finish(program.run(&mut renders)).await.unwrap();
+    assert_eq!(renders, 5);
+    assert_eq!(
+        exit.model.log,
+        (0..30).map(|i| i.to_string()).collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn time_budget_renders_between_batches() {
+    let mut model = Flood::new(12);
+    model.delay = Duration::from_millis(5);
+    let program = builder(model, ())
+        .with_frame_budget(FrameBudget::new().with_max_duration(Duration::from_millis(12)))
+        .build();
+    flood(&program, 12);
+    let mut renders = 0;
+    finish(program.run(&mut renders)).await.unwrap();
+    // At most three messages fit in each frame, so there are at least four frames plus the
+    // initial render and the quit message
+    assert!(renders >= 6, "only {renders} renders");
+}
+
+#[cfg(feature = "crossterm")]
+#[tokio::test]
+async fn prioritized_input_is_processed_first() {
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+
+    let program = builder(Flood::new(3), ())
+        .with_frame_budget(FrameBudget::new().with_prioritized_input(true))
+        .build();
+    flood(&program, 2);
+    let key = KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE);
+    program
+        .msg_tx()
+        .try_send(Message::TermEvent(Event::Key(key)))
+        .unwrap();
+    let exit = finish(program.run(&mut 0)).await.unwrap();
+    assert_eq!(exit.model.log, ["input", "0", "1"]);
+}
+
+fn assert_send<T: Send>(_: T) {}
+
+#[test]
+fn running_a_program_is_send() {
+    let mut renders = 0;
+    assert_send(builder(Countdown, 0).build().run(&mut renders));
+}